@@ -0,0 +1,137 @@
+//! Times searches of a generated database, or of an existing one with `cargo bench -- <db>`.
+//!
+//! Each query is searched as usual, where the case-insensitive substring terms are matched by a
+//! single automaton over a path that is lowercased once, see `TermSet` in the library, and with its
+//! terms in one `SearchQuery::all`, which is not compiled so that each term folds the path and
+//! searches it on its own. The entries that an anchored term cannot match are not skipped in the
+//! second case either, so `^DCIM` mostly times that. On the generated database:
+//!
+//! ```text
+//!                            automaton    per term
+//! jpg                         88.92 ms    97.96 ms
+//! camera                      89.04 ms    95.24 ms
+//! camera 2023 jpg            112.01 ms   107.54 ms
+//! camera jpg NOT thumb       120.09 ms   112.31 ms
+//! jpg OR png                 189.07 ms   188.90 ms
+//! ```
+//!
+//! Decoding the entries takes most of the time. The automaton is faster for a single term but a
+//! bit slower for several, since then the terms are searched for one at a time only until one is
+//! missing from the path.
+
+use anlocate::compress;
+use anlocate::search::{self, MatchType, SearchOptions, SearchQuery};
+use std::hint::black_box;
+use std::io::BufReader;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+const QUERIES: &[&str] = &[
+    "jpg",
+    "camera",
+    "camera 2023 jpg",
+    "camera jpg NOT thumb",
+    "jpg OR png",
+    "björk",
+    "^DCIM",
+];
+
+/// Each query is searched for at least this long.
+const MIN_TIME: Duration = Duration::from_secs(1);
+
+fn main() {
+    // cargo passes --bench
+    let database = match env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+        Some(path) => fs::read(path).expect("could not read database"),
+        None => generated_database(),
+    };
+
+    println!(
+        "{:<24} {:>11} {:>11} {:>9}",
+        "", "automaton", "per term", "hits"
+    );
+    for text in QUERIES {
+        let query = search::parse_query(text).unwrap();
+        let per_term = vec![SearchQuery::all(
+            search::parse_query(text).unwrap(),
+            MatchType::Include,
+        )];
+        let (time, hits) = time_search(&database, &query);
+        let (per_term_time, per_term_hits) = time_search(&database, &per_term);
+        assert_eq!(hits, per_term_hits, "{text}");
+        println!(
+            "{text:<24} {:>8.2} ms {:>8.2} ms {hits:>9}",
+            time.as_secs_f64() * 1000.0,
+            per_term_time.as_secs_f64() * 1000.0,
+        );
+    }
+}
+
+/// Returns the average time of searching the database and the number of hits.
+fn time_search(database: &[u8], query: &[SearchQuery]) -> (Duration, usize) {
+    let search = || {
+        let mut reader = BufReader::new(database);
+        search::search_with_options(&mut reader, query, &SearchOptions::default()).unwrap()
+    };
+    let hits = search().hits.len();
+    let start = Instant::now();
+    let mut runs = 0;
+    while runs < 3 || start.elapsed() < MIN_TIME {
+        black_box(search());
+        runs += 1;
+    }
+    (start.elapsed() / runs, hits)
+}
+
+/// A database of paths like on a phone, about a tenth of them with non-ASCII names.
+fn generated_database() -> Vec<u8> {
+    const DIRS: &[&str] = &[
+        "Android/data/com.example.app/cache",
+        "DCIM/Camera",
+        "DCIM/Screenshots",
+        "Documents/Work/2023",
+        "Download",
+        "Music/Björk/Homogenic",
+        "Music/Various Artists",
+        "Pictures/.thumbnails",
+        "Pictures/Telegram",
+        "Movies/Résumés",
+    ];
+    const NAMES: &[&str] = &[
+        "IMG",
+        "camera",
+        "Screenshot",
+        "thumb",
+        "report",
+        "track",
+        "日記",
+    ];
+    const EXTENSIONS: &[&str] = &["jpg", "png", "mp4", "pdf", "mp3", "txt"];
+
+    // a fixed linear congruential generator so that every run searches the same paths
+    let mut state: u64 = 1;
+    let mut next = |n: usize| {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) as usize % n
+    };
+    let mut paths: Vec<String> = (0..300_000)
+        .map(|i| {
+            format!(
+                "{}/{}_{}{:02}{:02}_{i}.{}",
+                DIRS[next(DIRS.len())],
+                NAMES[next(NAMES.len())],
+                2018 + next(7),
+                1 + next(12),
+                1 + next(28),
+                EXTENSIONS[next(EXTENSIONS.len())],
+            )
+        })
+        .collect();
+    paths.sort();
+    let lines: Vec<&[u8]> = paths.iter().map(|path| path.as_bytes()).collect();
+    let mut database = compress::compress_lines(&lines);
+    database.push(b'\n');
+    database
+}
@@ -1,9 +1,14 @@
-use std::env;
-use anlocate::build::DatabaseOptions;
+use anlocate::build::{DatabaseOptions, ScanRoot};
+use anlocate::journal::Journal;
+use anlocate::search::{
+    CaseMode, Hit, MatchScope, MatchType, MissingFiles, SearchOptions, SearchQuery, SearchStatus,
+};
 use anlocate::{build, search};
 use clap::{Parser, Subcommand};
+use std::env;
 use std::fs::File;
 use std::io::BufReader;
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[command(about, long_about = None)]
@@ -16,11 +21,76 @@ struct Cli {
 enum Commands {
     Search {
         db_path: String,
+        /// Terms that must be in the path, e.g. "(jpg OR png) camera NOT thumb" or
+        /// "dir:DCIM ext:jpg size:>1M modified:<7d", or a pattern with --glob, --regex or --fuzzy
         text: String,
+        /// Print the paths as stored in the database, without the root if it was removed
+        #[arg(long)]
+        relative: bool,
+        /// Print the results in database order instead of the most relevant first
+        #[arg(long)]
+        no_rank: bool,
+        /// Print at most this many results
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Stop searching after this many milliseconds and print what was found so far
+        #[arg(long)]
+        timeout_ms: Option<u64>,
+        /// Only search in this directory, given as the paths are stored in the database
+        #[arg(long)]
+        subtree: Option<String>,
+        /// Match text as a glob pattern (e.g. "**/DCIM/**/*.mp4") against the whole path
+        #[arg(short, long, group = "pattern")]
+        glob: bool,
+        /// Match text as a regular expression
+        #[arg(short, long, group = "pattern")]
+        regex: bool,
+        /// Match the characters of text in order, but not necessarily next to each other
+        #[arg(short, long, group = "pattern")]
+        fuzzy: bool,
+        /// Match against the file name only
+        #[arg(short, long, group = "scope")]
+        basename: bool,
+        /// Match against the directory part of the path only
+        #[arg(short, long, group = "scope")]
+        dir: bool,
+        /// Match against the path component with this index only (0 is the first)
+        #[arg(short, long, group = "scope")]
+        component: Option<usize>,
+        /// Match upper and lower case letters exactly instead of ignoring case
+        #[arg(short = 's', long, group = "case")]
+        case_sensitive: bool,
+        /// Match case exactly only if text has an uppercase letter
+        #[arg(short = 'S', long, group = "case")]
+        smart_case: bool,
+        /// Ignore accents and Unicode normalization, e.g. "ake" also matches "Åke"
+        #[arg(short = 'a', long)]
+        ignore_accents: bool,
+        /// Print the parts of the paths that matched in bold
+        #[arg(long)]
+        highlight: bool,
+        /// Only print files that still exist, e.g. not ones deleted since the database was built
+        #[arg(short, long)]
+        existing: bool,
+        /// Print the number of matching files instead of the files
+        #[arg(long, conflicts_with_all = ["limit", "existing", "highlight"])]
+        count: bool,
     },
     Build {
         db_path: String,
-        scan_root: String,
+        #[arg(required = true)]
+        scan_roots: Vec<String>,
+        #[arg(short, long, default_value_t = 1000000)]
+        mem_limit: usize,
+        #[arg(short, long)]
+        no_compress: bool,
+        #[arg(short, long)]
+        remove_root: bool,
+    },
+    Update {
+        db_path: String,
+        #[arg(required = true)]
+        scan_roots: Vec<String>,
         #[arg(short, long, default_value_t = 1000000)]
         mem_limit: usize,
         #[arg(short, long)]
@@ -33,14 +103,118 @@ enum Commands {
 fn main() {
     let cli = Cli::parse();
     match cli.command {
-        Commands::Search { db_path, text } => {
+        Commands::Search {
+            db_path,
+            text,
+            relative,
+            no_rank,
+            limit,
+            timeout_ms,
+            subtree,
+            glob,
+            regex,
+            fuzzy,
+            basename,
+            dir,
+            component,
+            case_sensitive,
+            smart_case,
+            ignore_accents,
+            highlight,
+            existing,
+            count,
+        } => {
+            let journal = Journal::load(&db_path).expect("could not read journal file");
+            let options = SearchOptions {
+                journal: Some(&journal),
+                absolute_paths: !relative,
+                rank: !no_rank,
+                max_results: limit,
+                deadline: timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms)),
+                highlight,
+                subtree: subtree.as_deref(),
+                missing: if existing {
+                    MissingFiles::Drop
+                } else {
+                    MissingFiles::Keep
+                },
+                ..Default::default()
+            };
             let mut db = BufReader::new(File::open(db_path).expect("could not open database file"));
-            match search::search(&mut db, &[text]) {
+            let scope = if basename {
+                MatchScope::Basename
+            } else if dir {
+                MatchScope::Directory
+            } else if let Some(index) = component {
+                MatchScope::Component(index)
+            } else {
+                MatchScope::Path
+            };
+            let case = if case_sensitive {
+                CaseMode::Sensitive
+            } else if smart_case {
+                CaseMode::Smart
+            } else {
+                CaseMode::Insensitive
+            };
+            let query = if regex {
+                SearchQuery::regex(&text, MatchType::Include, scope).map(|query| vec![query])
+            } else if fuzzy {
+                Ok(vec![SearchQuery::fuzzy(&text, MatchType::Include, scope)])
+            } else if glob {
+                Ok(vec![SearchQuery::glob(&text, MatchType::Include, scope)])
+            } else {
+                // keep the scope of dir: terms unless another one is given
+                search::parse_query(&text).map(|query| match scope {
+                    MatchScope::Path => query,
+                    scope => query
+                        .into_iter()
+                        .map(|query| query.with_scope(scope))
+                        .collect(),
+                })
+            };
+            let query: Vec<_> = match query {
+                Ok(query) => query
+                    .into_iter()
+                    .map(|query| query.with_case(case).with_ignore_accents(ignore_accents))
+                    .collect(),
+                Err(e) => {
+                    eprintln!("error: {:?}", e);
+                    return;
+                }
+            };
+            if count {
+                match search::count_matches(&mut db, &query, &options) {
+                    Ok(count) => {
+                        println!("{}", count.count);
+                        if count.status == SearchStatus::DeadlineExceeded {
+                            eprintln!("(search timed out, the count is incomplete)");
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("error: {:?}", e);
+                    }
+                }
+                return;
+            }
+            match search::search_with_options(&mut db, &query, &options) {
                 Ok(results) => {
-                   for hit in results {
-                       println!("{hit}");
-                   }
-                },
+                    for hit in &results.hits {
+                        println!("{}", highlighted(hit));
+                    }
+                    if results.status == SearchStatus::DeadlineExceeded {
+                        eprintln!("(search timed out, results are incomplete)");
+                    }
+                    if results.truncated && options.rank {
+                        eprintln!(
+                            "({} of {} results shown)",
+                            results.hits.len(),
+                            results.total_matches
+                        );
+                    } else if results.truncated {
+                        eprintln!("(more results not shown)");
+                    }
+                }
                 Err(e) => {
                     eprintln!("error: {:?}", e);
                 }
@@ -48,15 +222,16 @@ fn main() {
         }
         Commands::Build {
             db_path,
-            scan_root,
+            scan_roots,
             mem_limit,
             no_compress,
-            remove_root
+            remove_root,
         } => {
             print!("building...");
-            build::build_database(
+            let scan_roots: Vec<_> = scan_roots.iter().map(ScanRoot::new).collect();
+            build::build_database_from_roots(
                 db_path,
-                scan_root,
+                &scan_roots,
                 DatabaseOptions {
                     mem_limit,
                     compress: !no_compress,
@@ -67,5 +242,42 @@ fn main() {
             .expect("failed to build database");
             println!("done!");
         }
+        Commands::Update {
+            db_path,
+            scan_roots,
+            mem_limit,
+            no_compress,
+            remove_root,
+        } => {
+            print!("updating...");
+            let scan_roots: Vec<_> = scan_roots.iter().map(ScanRoot::new).collect();
+            build::update_database_from_roots(
+                db_path,
+                &scan_roots,
+                DatabaseOptions {
+                    mem_limit,
+                    compress: !no_compress,
+                    remove_root,
+                    temp_dir: env::temp_dir(),
+                },
+            )
+            .expect("failed to update database");
+            println!("done!");
+        }
+    }
+}
+
+/// Returns the path of the hit with its highlights in bold.
+fn highlighted(hit: &Hit) -> String {
+    let mut path = String::with_capacity(hit.path.len());
+    let mut end = 0;
+    for range in &hit.highlights {
+        path.push_str(&hit.path[end..range.start]);
+        path.push_str("\x1b[1m");
+        path.push_str(&hit.path[range.clone()]);
+        path.push_str("\x1b[0m");
+        end = range.end;
     }
+    path.push_str(&hit.path[end..]);
+    path
 }
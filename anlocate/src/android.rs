@@ -1,24 +1,45 @@
 use crate::build::DatabaseOptions;
-use crate::search;
-use crate::search::{MatchType, SearchQuery};
-use crate::{build, stat};
-use jni::objects::{GlobalRef, JBooleanArray, JObject, JObjectArray, JString, JValue};
+use crate::journal::Journal;
+use crate::search::{
+    CancelToken, CaseMode, MissingFiles, SearchOptions, SearchQuery, SearchSession, SearchStatus,
+};
+#[cfg(feature = "watch")]
+use crate::watch::{WatchOptions, Watcher};
+use crate::{build, journal, search, stat};
+use jni::objects::{GlobalRef, JObject, JString, JValue};
 use jni::strings::JNIString;
-use jni::sys::{jint, jlong, jobjectArray, jsize, JNI_ERR, JNI_VERSION_1_6};
+use jni::sys::{jboolean, jint, jlong, jobject, jsize, JNI_ERR, JNI_TRUE, JNI_VERSION_1_6};
 use jni::JavaVM;
 use jni::{JNIEnv, NativeMethod};
 use std::ffi::c_void;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::BufReader;
+use std::ops::Range;
 use std::panic;
-use std::sync::OnceLock;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 
 static ANDROID_ENTRY_POINT_CLASS: &str = "se/kalind/searchanywhere/data/files/AnlocateLibrary";
 
 static STRING_CLASS: OnceLock<GlobalRef> = OnceLock::new();
+static SEARCH_RESULT_CLASS: OnceLock<GlobalRef> = OnceLock::new();
 static LOG_CLASS: OnceLock<GlobalRef> = OnceLock::new();
 
+#[cfg(feature = "watch")]
+static WATCHER: Mutex<Option<Watcher>> = Mutex::new(None);
+// cancels the running search, if any
+static SEARCH_CANCEL: Mutex<Option<CancelToken>> = Mutex::new(None);
+// caches the last search, it is only valid for the database file (and modification time) it was
+// used with
+static SEARCH_SESSION: Mutex<Option<(String, Option<SystemTime>, SearchSession)>> =
+    Mutex::new(None);
+
+// NativeSearchResult.status values, must match the constants in AnlocateLibrary.kt
+const SEARCH_STATUS_COMPLETE: jint = 0;
+const SEARCH_STATUS_CANCELLED: jint = 1;
+const SEARCH_STATUS_TIMED_OUT: jint = 2;
+
 #[allow(non_snake_case)]
 #[no_mangle]
 pub extern "C" fn JNI_OnLoad(vm: JavaVM) -> jint {
@@ -32,6 +53,14 @@ pub extern "C" fn JNI_OnLoad(vm: JavaVM) -> jint {
     };
     let _ = STRING_CLASS.set(string_class);
 
+    let Ok(search_result_class) = class_global_ref(
+        &mut env,
+        "se/kalind/searchanywhere/data/files/NativeSearchResult",
+    ) else {
+        return JNI_ERR;
+    };
+    let _ = SEARCH_RESULT_CLASS.set(search_result_class);
+
     let Ok(log_class) = class_global_ref(&mut env, "android/util/Log") else {
         return JNI_ERR;
     };
@@ -41,26 +70,63 @@ pub extern "C" fn JNI_OnLoad(vm: JavaVM) -> jint {
         return JNI_ERR;
     };
 
-    let ret = env.register_native_methods(
-        entry_point,
-        &[
-            NativeMethod {
-                name: "nativeBuildDatabase".into(),
-                sig: "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V".into(),
-                fn_ptr: native_build_database as *mut c_void,
-            },
-            NativeMethod {
-                name: "nativeFindFiles".into(),
-                sig: "(Ljava/lang/String;[Ljava/lang/String;[Z)[Ljava/lang/String;".into(),
-                fn_ptr: native_find_files as *mut c_void,
-            },
-            NativeMethod {
-                name: "nativeGetStatIndexedFiles".into(),
-                sig: "(Ljava/lang/String;)J".into(),
-                fn_ptr: native_get_stat_indexed_files as *mut c_void,
-            },
-        ],
-    );
+    #[allow(unused_mut)]
+    let mut methods = vec![
+        NativeMethod {
+            name: "nativeBuildDatabase".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V".into(),
+            fn_ptr: native_build_database as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeUpdateDatabase".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V".into(),
+            fn_ptr: native_update_database as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeFindFiles".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;IIZJ)Lse/kalind/searchanywhere/data/files/NativeSearchResult;".into(),
+            fn_ptr: native_find_files as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeCountFiles".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;IZ)J".into(),
+            fn_ptr: native_count_files as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeCancelSearch".into(),
+            sig: "()V".into(),
+            fn_ptr: native_cancel_search as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeJournalAdd".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;)V".into(),
+            fn_ptr: native_journal_add as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeJournalRemove".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;)V".into(),
+            fn_ptr: native_journal_remove as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeGetStatIndexedFiles".into(),
+            sig: "(Ljava/lang/String;)J".into(),
+            fn_ptr: native_get_stat_indexed_files as *mut c_void,
+        },
+    ];
+    #[cfg(feature = "watch")]
+    methods.extend([
+        NativeMethod {
+            name: "nativeStartWatcher".into(),
+            sig: "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V".into(),
+            fn_ptr: native_start_watcher as *mut c_void,
+        },
+        NativeMethod {
+            name: "nativeStopWatcher".into(),
+            sig: "()V".into(),
+            fn_ptr: native_stop_watcher as *mut c_void,
+        },
+    ]);
+    let ret = env.register_native_methods(entry_point, &methods);
     if ret.is_err() {
         logcat_d(&mut env, "JNI_OnLoad error");
         return JNI_ERR;
@@ -102,17 +168,51 @@ pub extern "C" fn native_build_database<'local>(
     logcat_d(&mut env, "build db ok");
 }
 
+pub extern "C" fn native_update_database<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    db_file: JString<'local>,
+    scan_root: JString<'local>,
+    temp_dir: JString<'local>,
+) {
+    let Ok(db_file) = get_string(&mut env, &db_file) else {
+        return;
+    };
+    let Ok(scan_root) = get_string(&mut env, &scan_root) else {
+        return;
+    };
+    let Ok(temp_dir) = get_string(&mut env, &temp_dir) else {
+        return;
+    };
+
+    let result = panic::catch_unwind(|| {
+        let options = DatabaseOptions {
+            temp_dir: temp_dir.into(),
+            remove_root: true,
+            ..Default::default()
+        };
+        build::update_database(db_file, scan_root, options)
+    });
+    throw_if_err(&mut env, &result);
+    logcat_d(&mut env, "update db ok");
+}
+
 // this function gets called frequently, so it should be as fast as possible
 pub extern "C" fn native_find_files<'local>(
     mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
     db_file: JString<'local>,
-    query: JObjectArray<'local>,
-    include_exclude: JBooleanArray<'local>,
-) -> jobjectArray {
+    query: JString<'local>,
+    max_results: jint,
+    case_mode: jint,
+    ignore_accents: jboolean,
+    timeout_ms: jlong,
+) -> jobject {
     let null = JObject::null().into_raw();
 
-    let Some(string_class) = STRING_CLASS.get() else {
+    let (Some(string_class), Some(search_result_class)) =
+        (STRING_CLASS.get(), SEARCH_RESULT_CLASS.get())
+    else {
         throw(
             &mut env,
             "java/lang/IllegalStateException",
@@ -125,86 +225,296 @@ pub extern "C" fn native_find_files<'local>(
     let Ok(db_file) = get_string(&mut env, &db_file) else {
         return null;
     };
-    let Ok(query_len) = env.get_array_length(&query) else {
+    let Ok(query) = get_string(&mut env, &query) else {
         return null;
     };
-    let Ok(include_exclude_len) = env.get_array_length(&include_exclude) else {
-        return null;
-    };
-
-    if query_len != include_exclude_len {
-        throw(
-            &mut env,
-            "java/lang/IllegalStateException",
-            "include_exclude length must be the same as query length",
-        );
-        return null;
-    }
 
-    let mut include_exclude_vec = vec![0u8; query_len as usize];
-    if env
-        .get_boolean_array_region(&include_exclude, 0, &mut include_exclude_vec)
-        .is_err()
-    {
+    // the query is often incomplete while it is being typed, e.g. `(jpg OR`, and then the
+    // previous results are kept
+    let Some(search_query) = parse_search_query(&query, case_mode, ignore_accents) else {
         return null;
-    }
-
-    let mut query_vec: Vec<String> = Vec::new();
+    };
 
-    for i in 0..query_len {
-        let Ok(obj) = env.get_object_array_element(&query, i) else {
-            return null;
-        };
-        let Ok(query_str) = get_string(&mut env, &JString::from(obj)) else {
-            return null;
-        };
-        query_vec.push(query_str);
+    // a new search makes the previous one stale so it is cancelled
+    let cancel = CancelToken::new();
+    if let Ok(mut current) = SEARCH_CANCEL.lock() {
+        if let Some(previous) = current.replace(cancel.clone()) {
+            previous.cancel();
+        }
     }
 
-    let search_query = make_search_queries(&query_vec, &include_exclude_vec);
+    // a negative timeout means no deadline
+    let deadline = u64::try_from(timeout_ms)
+        .ok()
+        .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms));
 
     // call the lib search function
     let result = panic::catch_unwind(|| {
-        let mut reader = BufReader::new(File::open(db_file).expect("failed to open database file"));
-        search::search(&mut reader, &search_query)
+        let journal = Journal::load(&db_file).expect("failed to read journal file");
+        let options = SearchOptions {
+            journal: Some(&journal),
+            cancel: Some(&cancel),
+            deadline,
+            rank: true,
+            highlight: true,
+            // the database may be older than the files, and deleted ones cannot be opened
+            missing: MissingFiles::Drop,
+            // a negative max means no limit
+            max_results: usize::try_from(max_results).ok(),
+            ..Default::default()
+        };
+        let file = File::open(&db_file).expect("failed to open database file");
+        let modified = file.metadata().and_then(|m| m.modified()).ok();
+        let mut session = SEARCH_SESSION
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !matches!(&*session, Some((path, mtime, _)) if *path == db_file && *mtime == modified) {
+            *session = Some((db_file.clone(), modified, SearchSession::new()));
+        }
+        let (_, _, session) = session.as_mut().expect("session was just set");
+        let mut reader = BufReader::new(file);
+        session.search(&mut reader, &search_query, &options)
     });
 
     throw_if_err(&mut env, &result);
 
-    if let Ok(Ok(files)) = result {
+    if let Ok(Ok(results)) = result {
+        let status = match results.status {
+            SearchStatus::Complete => SEARCH_STATUS_COMPLETE,
+            SearchStatus::Cancelled => SEARCH_STATUS_CANCELLED,
+            SearchStatus::DeadlineExceeded => SEARCH_STATUS_TIMED_OUT,
+        };
+        let hits = results.hits;
         // will probably never happen but better safe than sorry
-        let files = if files.len() > (jsize::MAX as usize) {
-            &files[0..(jsize::MAX as usize)]
+        let hits = if hits.len() > (jsize::MAX as usize) {
+            &hits[0..(jsize::MAX as usize)]
         } else {
-            files.as_slice()
+            hits.as_slice()
         };
+        let len: jsize = hits.len().try_into().unwrap(); // safety: never panics
 
-        let Ok(obj_array) = env.new_object_array(
-            files.len().try_into().unwrap(), // safety: never panics
-            string_class,
-            JObject::null(),
-        ) else {
+        let Ok(files) = env.new_object_array(len, string_class, JObject::null()) else {
+            return null;
+        };
+        let Ok(highlights) = env.new_object_array(len, "[I", JObject::null()) else {
             return null;
         };
 
-        for (i, file) in files.iter().enumerate() {
-            let Ok(java_string) = env.new_string(file) else {
+        for (i, hit) in hits.iter().enumerate() {
+            let index: jsize = i.try_into().unwrap(); // safety: never panics
+            let Ok(java_string) = env.new_string(&hit.path) else {
                 return null;
             };
-            let index: jsize = i.try_into().unwrap(); // safety: never panics
             if env
-                .set_object_array_element(&obj_array, index, java_string)
+                .set_object_array_element(&files, index, java_string)
                 .is_err()
             {
                 return null;
             }
+
+            let ranges = utf16_ranges(&hit.path, &hit.highlights);
+            let Ok(int_array) = env.new_int_array(ranges.len().try_into().unwrap_or(0)) else {
+                return null;
+            };
+            if env.set_int_array_region(&int_array, 0, &ranges).is_err()
+                || env
+                    .set_object_array_element(&highlights, index, int_array)
+                    .is_err()
+            {
+                return null;
+            }
         }
-        obj_array.into_raw()
+
+        let Ok(search_result) = env.new_object(
+            search_result_class,
+            "([Ljava/lang/String;[[II)V",
+            &[
+                JValue::Object(&files),
+                JValue::Object(&highlights),
+                JValue::Int(status),
+            ],
+        ) else {
+            return null;
+        };
+        search_result.into_raw()
     } else {
         null
     }
 }
 
+// returns the number of files that match the query, or -1 if the query is incomplete. It does not
+// cancel (and cannot be cancelled by) nativeFindFiles
+pub extern "C" fn native_count_files<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    db_file: JString<'local>,
+    query: JString<'local>,
+    case_mode: jint,
+    ignore_accents: jboolean,
+) -> jlong {
+    let Ok(db_file) = get_string(&mut env, &db_file) else {
+        return -1;
+    };
+    let Ok(query) = get_string(&mut env, &query) else {
+        return -1;
+    };
+    let Some(search_query) = parse_search_query(&query, case_mode, ignore_accents) else {
+        return -1;
+    };
+
+    let result = panic::catch_unwind(|| {
+        let journal = Journal::load(&db_file).expect("failed to read journal file");
+        let options = SearchOptions {
+            journal: Some(&journal),
+            ..Default::default()
+        };
+        let file = File::open(&db_file).expect("failed to open database file");
+        search::count_matches(&mut BufReader::new(file), &search_query, &options)
+    });
+
+    throw_if_err(&mut env, &result);
+
+    if let Ok(Ok(count)) = result {
+        count.count.try_into().unwrap_or(jlong::MAX)
+    } else {
+        -1
+    }
+}
+
+// parses a query from the app, returns None if it is invalid
+fn parse_search_query(
+    query: &str,
+    case_mode: jint,
+    ignore_accents: jboolean,
+) -> Option<Vec<SearchQuery<'_>>> {
+    // the values of the CASE_ constants in AnlocateLibrary
+    let case = match case_mode {
+        1 => CaseMode::Sensitive,
+        2 => CaseMode::Smart,
+        _ => CaseMode::Insensitive,
+    };
+    let search_query = search::parse_query(query).ok()?;
+    let search_query = search_query
+        .into_iter()
+        .map(|query| {
+            query
+                .with_case(case)
+                .with_ignore_accents(ignore_accents == JNI_TRUE)
+        })
+        .collect();
+    Some(search_query)
+}
+
+// converts byte ranges of path to start and end pairs of UTF-16 indexes, which is what Java and
+// Kotlin strings are indexed by
+fn utf16_ranges(path: &str, ranges: &[Range<usize>]) -> Vec<jint> {
+    let mut indexes = Vec::with_capacity(ranges.len() * 2);
+    let mut utf16_index = 0;
+    let mut byte_index = 0;
+    let mut utf16_index_of = |byte: usize| {
+        utf16_index += path[byte_index..byte].encode_utf16().count();
+        byte_index = byte;
+        jint::try_from(utf16_index).unwrap_or(jint::MAX)
+    };
+    // the ranges are sorted so the path is only walked once
+    for range in ranges {
+        indexes.push(utf16_index_of(range.start));
+        indexes.push(utf16_index_of(range.end));
+    }
+    indexes
+}
+
+// cancels the running search which then returns the hits found so far with status cancelled
+pub extern "C" fn native_cancel_search<'local>(_env: JNIEnv<'local>, _obj: JObject<'local>) {
+    // the lock is only poisoned if a search panicked, in which case there is nothing to cancel
+    if let Ok(cancel) = SEARCH_CANCEL.lock() {
+        if let Some(cancel) = cancel.as_ref() {
+            cancel.cancel();
+        }
+    }
+}
+
+// the path is the full path of the file, it is stored relative to the root it is in
+pub extern "C" fn native_journal_add<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    db_file: JString<'local>,
+    path: JString<'local>,
+) {
+    let Ok(db_file) = get_string(&mut env, &db_file) else {
+        return;
+    };
+    let Ok(path) = get_string(&mut env, &path) else {
+        return;
+    };
+    let result = panic::catch_unwind(|| journal::record_add(db_file, &path));
+    throw_if_err(&mut env, &result);
+}
+
+// the path is the full path of the file, it is stored relative to the root it is in
+pub extern "C" fn native_journal_remove<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    db_file: JString<'local>,
+    path: JString<'local>,
+) {
+    let Ok(db_file) = get_string(&mut env, &db_file) else {
+        return;
+    };
+    let Ok(path) = get_string(&mut env, &path) else {
+        return;
+    };
+    let result = panic::catch_unwind(|| journal::record_remove(db_file, &path));
+    throw_if_err(&mut env, &result);
+}
+
+// starts watching the scan root for changes (stopping any previously started watcher)
+#[cfg(feature = "watch")]
+pub extern "C" fn native_start_watcher<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    db_file: JString<'local>,
+    scan_root: JString<'local>,
+    temp_dir: JString<'local>,
+) {
+    let Ok(db_file) = get_string(&mut env, &db_file) else {
+        return;
+    };
+    let Ok(scan_root) = get_string(&mut env, &scan_root) else {
+        return;
+    };
+    let Ok(temp_dir) = get_string(&mut env, &temp_dir) else {
+        return;
+    };
+
+    let result = panic::catch_unwind(|| {
+        let mut watcher = WATCHER.lock().expect("watcher lock poisoned");
+        if let Some(old) = watcher.take() {
+            let _ = old.stop();
+        }
+        let options = WatchOptions {
+            database: DatabaseOptions {
+                temp_dir: temp_dir.into(),
+                remove_root: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let roots = [build::ScanRoot::new(scan_root)];
+        Watcher::start(db_file, &roots, options).map(|w| *watcher = Some(w))
+    });
+    throw_if_err(&mut env, &result);
+}
+
+#[cfg(feature = "watch")]
+pub extern "C" fn native_stop_watcher<'local>(mut env: JNIEnv<'local>, _obj: JObject<'local>) {
+    let result = panic::catch_unwind(|| {
+        let mut watcher = WATCHER.lock().expect("watcher lock poisoned");
+        watcher.take().map_or(Ok(()), |w| w.stop())
+    });
+    throw_if_err(&mut env, &result);
+}
+
 pub extern "C" fn native_get_stat_indexed_files<'local>(
     mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
@@ -305,21 +615,3 @@ fn logcat_d(env: &mut JNIEnv, message: impl Into<JNIString>) {
         ],
     );
 }
-
-fn make_search_queries<'a>(
-    query_vec: &'a [String],
-    include_exclude_vec: &[u8],
-) -> Vec<SearchQuery<'a>> {
-    query_vec
-        .iter()
-        .zip(include_exclude_vec.iter())
-        .map(|(q, i)| {
-            let match_type = if *i == 1 {
-                MatchType::Include
-            } else {
-                MatchType::Exclude
-            };
-            SearchQuery::new(q.as_str(), match_type)
-        })
-        .collect()
-}
@@ -1,11 +1,15 @@
-use crate::{compress, util};
+use crate::header::{self, DatabaseHeader, RootTableEntry};
+use crate::{compress, journal, util};
 use nanorand::Rng;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, ErrorKind, Write};
-use std::os::unix::ffi::OsStrExt;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+use std::time::UNIX_EPOCH;
 use std::{env, fs, io};
 use std::{mem, thread};
 
@@ -18,7 +22,8 @@ pub struct DatabaseOptions {
     pub mem_limit: usize,
     /// Whether to compress the database or not.
     pub compress: bool,
-    /// If true the scan root directory prefix path will not be included in the database.
+    /// If true the scan root directory prefix path will not be included in the entries. The root
+    /// is stored in the database header instead.
     pub remove_root: bool,
     /// The path to the dir where temporary .part files are written.
     pub temp_dir: PathBuf,
@@ -35,31 +40,264 @@ impl Default for DatabaseOptions {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+/// A directory tree to index
+pub struct ScanRoot {
+    /// The directory to index.
+    pub path: PathBuf,
+    /// Directories that are not indexed. Relative paths are relative to `path`.
+    pub prune_paths: Vec<PathBuf>,
+    /// Names of directories that are not indexed wherever they are found, e.g. `.git`.
+    pub prune_names: Vec<OsString>,
+    /// A short name for the root, e.g. "sdcard", that can be shown instead of the root path.
+    pub alias: Option<String>,
+}
+
+impl ScanRoot {
+    pub fn new<P: Into<PathBuf>>(path: P) -> ScanRoot {
+        ScanRoot {
+            path: path.into(),
+            prune_paths: Vec::new(),
+            prune_names: Vec::new(),
+            alias: None,
+        }
+    }
+
+    pub(crate) fn is_pruned(&self, dir: &Path) -> bool {
+        dir.file_name()
+            .is_some_and(|name| self.prune_names.iter().any(|prune| prune == name))
+            || self
+                .prune_paths
+                .iter()
+                .any(|prune| self.path.join(prune) == dir)
+    }
+}
+
 pub fn build_database<P1: AsRef<Path>, P2: Into<PathBuf>>(
     db_file: P1,
     scan_root: P2,
     options: DatabaseOptions,
 ) -> io::Result<()> {
+    build_database_from_roots(db_file, &[ScanRoot::new(scan_root)], options)
+}
+
+/// Builds a database that indexes all `roots`. The roots are stored in a root table in the
+/// database header, which lets searches tell which root a path belongs to.
+///
+/// A database with a single root without alias or prune rules is stored without a header.
+pub fn build_database_from_roots<P: AsRef<Path>>(
+    db_file: P,
+    roots: &[ScanRoot],
+    options: DatabaseOptions,
+) -> io::Result<()> {
+    let remove_root = options.remove_root;
+    write_roots(db_file.as_ref(), roots, options, |_, root, collector| {
+        walk_dir(root, remove_root, collector)
+    })
+}
+
+pub fn update_database<P1: AsRef<Path>, P2: Into<PathBuf>>(
+    db_file: P1,
+    scan_root: P2,
+    options: DatabaseOptions,
+) -> io::Result<()> {
+    update_database_from_roots(db_file, &[ScanRoot::new(scan_root)], options)
+}
+
+/// Updates a database previously built by `build_database_from_roots` with the same roots and
+/// options.
+///
+/// Like mlocate, only directories whose modification time changed since the last build are read
+/// from the file system. The files in all other directories are copied from the old database.
+/// Note that this means that changes that do not touch the modification time of the parent
+/// directory (e.g. restoring an old mtime with `touch -m`) are not picked up.
+///
+/// If the database has no valid directory table (or does not exist) or was built from other roots
+/// or with another `compress` or `remove_root` option a full build is done instead.
+pub fn update_database_from_roots<P: AsRef<Path>>(
+    db_file: P,
+    roots: &[ScanRoot],
+    options: DatabaseOptions,
+) -> io::Result<()> {
+    let db_file = db_file.as_ref();
+
+    let old_dirs = match read_dir_table(&dir_table_path(db_file)) {
+        Ok(table) if db_file.is_file() && table.compressed == options.compress => table.dirs,
+        Ok(_) => return build_database_from_roots(db_file, roots, options),
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::InvalidData) => {
+            return build_database_from_roots(db_file, roots, options)
+        }
+        Err(err) => return Err(err),
+    };
+    let old_header = header::read_header(&mut BufReader::new(File::open(db_file)?))?;
+    let same_roots = match &old_header {
+        Some(header) => header
+            .roots
+            .iter()
+            .map(|entry| (&entry.root.path, entry.root_removed))
+            .eq(roots.iter().map(|root| (&root.path, options.remove_root))),
+        // databases built with remove_root before the root was stored have no header
+        None => roots.len() == 1 && !options.remove_root,
+    };
+    if !same_roots {
+        return build_database_from_roots(db_file, roots, options);
+    }
+
+    let remove_root = options.remove_root;
+    // the sections are written in the same order as in the old database, so it is read only once
+    let mut old_paths = DatabasePaths::open(db_file, options.compress)?;
+
+    write_roots(db_file, roots, options, |index, root, collector| {
+        let mut unchanged = HashSet::new();
+        let mut changed = Vec::new();
+        for (dir, &old_mtime) in old_dirs
+            .iter()
+            .filter(|(dir, _)| dir.starts_with(&root.path))
+        {
+            // if the dir can no longer be stat:ed it has been removed (which also changed the
+            // mtime of its parent dir)
+            let Ok(metadata) = fs::metadata(dir) else {
+                continue;
+            };
+            if !metadata.is_dir() {
+                continue;
+            }
+            let mtime = mtime(&metadata);
+            if mtime != 0 && mtime == old_mtime {
+                collector.add_dir(dir.clone(), mtime);
+                unchanged.insert(dir.as_path());
+            } else {
+                changed.push(dir);
+            }
+        }
+
+        // copy the files of unchanged dirs from the old database
+        let status = old_paths.for_each_in_section(index, |path| {
+            let path = Path::new(OsStr::from_bytes(path));
+            let fs_path = if remove_root {
+                root.path.join(path)
+            } else {
+                path.to_path_buf()
+            };
+            match fs_path.parent() {
+                Some(parent) if unchanged.contains(parent) => collector.add_file(path.into()),
+                _ => WalkStatus::Ok,
+            }
+        })?;
+        if status == WalkStatus::Aborted {
+            return Ok(WalkStatus::Aborted);
+        }
+
+        // re-read the changed dirs. Subdirs that are in the old directory table are handled by
+        // their own entry in the table, new subdirs are walked recursively.
+        let root_prefix = if remove_root {
+            Some(root.path.as_path())
+        } else {
+            None
+        };
+        for dir in changed {
+            let status = walk_dir_internal(dir.clone(), root_prefix, collector, &|subdir| {
+                old_dirs.contains_key(subdir) || root.is_pruned(subdir)
+            })?;
+            if status == WalkStatus::Aborted {
+                return Ok(WalkStatus::Aborted);
+            }
+        }
+        Ok(WalkStatus::Ok)
+    })
+}
+
+/// Writes the database with the files that `collect` collects for each root, followed by the
+/// directory table. When done, the journal is compacted.
+fn write_roots<F>(
+    db_file: &Path,
+    roots: &[ScanRoot],
+    options: DatabaseOptions,
+    mut collect: F,
+) -> io::Result<()>
+where
+    F: FnMut(usize, &ScanRoot, &mut Collector) -> io::Result<WalkStatus>,
+{
+    // the file system walk picks up all changes that were recorded before it started
+    let journal_len = journal::recorded_len(db_file)?;
+
+    let mut dirs = Vec::new();
+    if let [root] = roots {
+        if !options.remove_root
+            && root.alias.is_none()
+            && root.prune_paths.is_empty()
+            && root.prune_names.is_empty()
+        {
+            // nothing to store in the root table so the database is written without a header
+            (_, dirs) = write_section(db_file, &options, |collector| collect(0, root, collector))?;
+            write_dir_table(&dir_table_path(db_file), options.compress, dirs)?;
+            return journal::discard_recorded(db_file, journal_len);
+        }
+    }
+
+    // the entries of each root are written to a section file which are then concatenated
+    let temp_dir = options.temp_dir.join(format!(
+        "anlocate-{}",
+        nanorand::tls_rng().generate::<u64>()
+    ));
+    fs::create_dir_all(&temp_dir)?;
+    let _dropper = RemoveDirOnDrop(&temp_dir);
+
+    let mut header = DatabaseHeader::default();
+    let mut sections = Vec::new();
+    for (i, root) in roots.iter().enumerate() {
+        let section = temp_dir.join(format!("{i}.section"));
+        let (entries, root_dirs) =
+            write_section(&section, &options, |collector| collect(i, root, collector))?;
+        header.roots.push(RootTableEntry {
+            root: root.clone(),
+            entries,
+            root_removed: options.remove_root,
+        });
+        dirs.extend(root_dirs);
+        sections.push(section);
+    }
+
+    if let Some(parent) = db_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut database = BufWriter::new(File::create(db_file)?);
+    header::write_header(&mut database, &header)?;
+    for section in sections {
+        io::copy(&mut File::open(section)?, &mut database)?;
+    }
+    database.flush()?;
+
+    write_dir_table(&dir_table_path(db_file), options.compress, dirs)?;
+    journal::discard_recorded(db_file, journal_len)
+}
+
+/// Runs `collect` on the calling thread while the files it collects are written to `section_file`
+/// on a writer thread. Returns the number of entries written and the collected directories.
+fn write_section<F>(
+    section_file: &Path,
+    options: &DatabaseOptions,
+    collect: F,
+) -> io::Result<(u64, Vec<(PathBuf, u128)>)>
+where
+    F: FnOnce(&mut Collector) -> io::Result<WalkStatus>,
+{
     let (tx, rx) = mpsc::channel::<Vec<PathBuf>>();
 
     // db writer thread
-    let database = db_file.as_ref().to_owned();
+    let database = section_file.to_owned();
     let options_clone = options.clone();
     let handle =
-        thread::spawn(move || -> io::Result<()> { write_database(rx, database, options_clone) });
+        thread::spawn(move || -> io::Result<u64> { write_database(rx, database, options_clone) });
 
-    // scan root dir and send files to writer thread
-    walk_dir(scan_root, options, |files| {
-        match tx.send(files) {
-            Ok(_) => WalkStatus::Ok,
-            // the writer thread returned an error or panicked so abort the walk
-            Err(_) => WalkStatus::Aborted,
-        }
-    })?;
+    let mut collector = Collector::new(tx, options.mem_limit);
+    let status = collect(&mut collector);
+    // flush any remaining file paths and close the channel to the writer thread
+    let dirs = collector.finish(matches!(status, Ok(WalkStatus::Ok)));
 
-    drop(tx);
-    handle.join().unwrap()?;
-    Ok(())
+    let entries = handle.join().unwrap()?;
+    status?;
+    Ok((entries, dirs))
 }
 
 struct RemoveDirOnDrop<'a>(&'a Path);
@@ -76,7 +314,7 @@ fn write_database(
     rx: Receiver<Vec<PathBuf>>,
     db_path: PathBuf,
     options: DatabaseOptions,
-) -> io::Result<()> {
+) -> io::Result<u64> {
     let temp_dir = options.temp_dir.join(format!(
         "anlocate-{}",
         nanorand::tls_rng().generate::<u64>()
@@ -93,13 +331,12 @@ fn write_database(
         files.sort_unstable_by(|a, b| a.as_os_str().cmp(b.as_os_str()));
         for path in files {
             part_file.write_all(path.as_os_str().as_bytes())?;
-            part_file.write_all(&[b'\n'])?;
+            part_file.write_all(b"\n")?;
         }
         part_file.flush()?;
     }
 
-    write_database_from_parts(&db_path, &part_file_paths, options.compress)?;
-    Ok(())
+    write_database_from_parts(&db_path, &part_file_paths, options.compress)
 }
 
 struct LineHolder {
@@ -131,17 +368,18 @@ impl LineHolder {
     }
 }
 
+/// Merges the sorted part files into the database and returns the number of entries written.
 fn write_database_from_parts(
     db_file: &Path,
     part_files: &[PathBuf],
     compress: bool,
-) -> io::Result<()> {
+) -> io::Result<u64> {
     if let Some(parent) = db_file.parent() {
         fs::create_dir_all(parent)?;
     }
     let mut database = File::create(db_file)?;
     if part_files.is_empty() {
-        return Ok(());
+        return Ok(0);
     }
 
     let mut holders: Vec<LineHolder> = part_files
@@ -156,6 +394,7 @@ fn write_database_from_parts(
 
     let mut line_buf = Vec::new();
     let mut prev = Vec::new();
+    let mut entries = 0;
     loop {
         let smallest = holders
             .iter_mut()
@@ -185,10 +424,11 @@ fn write_database_from_parts(
             prev = mem::take(&mut smallest.line);
             database.write_all(&line_buf)?;
             line_buf.clear();
+            entries += 1;
             smallest.read_line()?;
         }
     }
-    Ok(())
+    Ok(entries)
 }
 
 #[derive(Debug, PartialEq)]
@@ -197,90 +437,329 @@ enum WalkStatus {
     Aborted,
 }
 
-fn walk_dir<P, F>(root: P, options: DatabaseOptions, on_flush: F) -> io::Result<()>
+/// Collects the file paths found by the file system walker and sends them to the db writer thread
+/// in batches of at most `mem_limit` bytes. Also collects the directories that were read.
+struct Collector {
+    tx: mpsc::Sender<Vec<PathBuf>>,
+    mem_limit: usize,
+    files: Vec<PathBuf>,
+    size: usize,
+    dirs: Vec<(PathBuf, u128)>,
+}
+
+impl Collector {
+    fn new(tx: mpsc::Sender<Vec<PathBuf>>, mem_limit: usize) -> Collector {
+        Collector {
+            tx,
+            mem_limit,
+            files: Vec::new(),
+            size: 0,
+            dirs: Vec::new(),
+        }
+    }
+
+    fn add_file(&mut self, path: PathBuf) -> WalkStatus {
+        let elem_size = path.as_os_str().as_bytes().len() + mem::size_of::<PathBuf>();
+        let new_size = self.size + elem_size;
+        let status = if new_size >= self.mem_limit {
+            self.size = elem_size;
+            self.flush()
+        } else {
+            self.size = new_size;
+            WalkStatus::Ok
+        };
+        self.files.push(path);
+        status
+    }
+
+    fn add_dir(&mut self, path: PathBuf, mtime: u128) {
+        self.dirs.push((path, mtime));
+    }
+
+    fn flush(&mut self) -> WalkStatus {
+        match self.tx.send(mem::take(&mut self.files)) {
+            Ok(_) => WalkStatus::Ok,
+            // the writer thread returned an error or panicked so abort the walk
+            Err(_) => WalkStatus::Aborted,
+        }
+    }
+
+    /// Flushes any remaining file paths (if `flush` is true) and returns the collected dirs.
+    fn finish(mut self, flush: bool) -> Vec<(PathBuf, u128)> {
+        if flush && !self.files.is_empty() {
+            self.flush();
+        }
+        self.dirs
+    }
+}
+
+fn walk_dir(
+    root: &ScanRoot,
+    remove_root: bool,
+    collector: &mut Collector,
+) -> io::Result<WalkStatus> {
+    if !root.path.is_dir() {
+        panic!("root is not a directory: {:?}", root.path);
+    }
+    let remove_root = if remove_root {
+        Some(root.path.as_path())
+    } else {
+        None
+    };
+    walk_dir_internal(root.path.clone(), remove_root, collector, &|dir| {
+        root.is_pruned(dir)
+    })
+}
+
+/// Walks `dir` recursively, skipping the subdirs for which `skip_dir` returns true.
+fn walk_dir_internal<S>(
+    dir: PathBuf,
+    remove_root: Option<&Path>, // if set the root (prefix path) will not be in the output
+    collector: &mut Collector,
+    skip_dir: &S,
+) -> io::Result<WalkStatus>
 where
-    P: Into<PathBuf>,
-    F: Fn(Vec<PathBuf>) -> WalkStatus,
+    S: Fn(&Path) -> bool,
 {
-    fn walk_dir_internal<F>(
-        dir: PathBuf,
-        remove_root: Option<&Path>, // if set the root (prefix path) will not be in the output
-        mem_limit: usize,
-        on_flush: &F,
-        files: &mut Vec<PathBuf>,
-        size: &mut usize,
-    ) -> io::Result<WalkStatus>
-    where
-        F: Fn(Vec<PathBuf>) -> WalkStatus,
-    {
-        let entries = match fs::read_dir(dir) {
-            Ok(entries) => entries,
-            // if permission denied, ignore and continue walk
-            Err(err) if err.kind() == ErrorKind::PermissionDenied => return Ok(WalkStatus::Ok),
-            Err(err) => return Err(err),
-        };
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                let status = walk_dir_internal(path, remove_root, mem_limit, on_flush, files, size);
-                if let Err(_) | Ok(WalkStatus::Aborted) = status {
-                    return status;
-                }
+    let mtime = fs::metadata(&dir).map(|metadata| mtime(&metadata));
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        // if permission denied, ignore and continue walk. The dir is stored with mtime 0 so that
+        // it is read again on the next update.
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            collector.add_dir(dir, 0);
+            return Ok(WalkStatus::Ok);
+        }
+        Err(err) => return Err(err),
+    };
+    collector.add_dir(dir, mtime?);
+
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            if skip_dir(&path) {
+                continue;
+            }
+            let status = walk_dir_internal(path, remove_root, collector, skip_dir);
+            if let Err(_) | Ok(WalkStatus::Aborted) = status {
+                return status;
+            }
+        } else {
+            let path = if let Some(root) = remove_root {
+                path.strip_prefix(root)
+                    .expect("root was not prefix")
+                    .to_path_buf()
             } else {
-                let path = if let Some(root) = remove_root {
-                    path.strip_prefix(root)
-                        .expect("root was not prefix")
-                        .to_path_buf()
-                } else {
-                    path
-                };
-                let elem_size = path.as_os_str().as_bytes().len() + mem::size_of::<PathBuf>();
-                let new_size = *size + elem_size;
-                if new_size >= mem_limit {
-                    *size = elem_size;
-                    if on_flush(mem::take(files)) == WalkStatus::Aborted {
-                        return Ok(WalkStatus::Aborted);
-                    }
-                } else {
-                    *size = new_size;
-                }
-                files.push(path);
+                path
+            };
+            if collector.add_file(path) == WalkStatus::Aborted {
+                return Ok(WalkStatus::Aborted);
             }
         }
+    }
 
-        Ok(WalkStatus::Ok)
+    Ok(WalkStatus::Ok)
+}
+
+/// Returns the modification time in nanoseconds since the Unix epoch, or 0 if it is not available.
+fn mtime(metadata: &Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos())
+}
+
+/// Reads the (uncompressed) paths of the entries of a database in order, together with the index
+/// of the root (in the root table) that they belong to.
+struct DatabasePaths {
+    reader: BufReader<File>,
+    compressed: bool,
+    section_sizes: Vec<u64>,
+    section: usize,
+    section_remaining: u64,
+    buf: Vec<u8>,
+    /// The path of the last entry that was read.
+    path: Vec<u8>,
+    /// True if the last entry that was read belongs to a later section than the one that was
+    /// asked for, so that it is not passed on until that section is read.
+    pending: bool,
+}
+
+impl DatabasePaths {
+    fn open(db_file: &Path, compressed: bool) -> io::Result<DatabasePaths> {
+        let mut reader = BufReader::new(File::open(db_file)?);
+        // a database without a header only has one root
+        let section_sizes: Vec<u64> = match header::read_header(&mut reader)? {
+            Some(header) => header.roots.iter().map(|entry| entry.entries).collect(),
+            None => vec![u64::MAX],
+        };
+        Ok(DatabasePaths {
+            reader,
+            compressed,
+            section_remaining: section_sizes.first().copied().unwrap_or(0),
+            section_sizes,
+            section: 0,
+            buf: Vec::new(),
+            path: Vec::new(),
+            pending: false,
+        })
+    }
+
+    /// Reads the next entry into `path` and returns the index of its root, or None if the end of
+    /// the database is reached.
+    fn read_next(&mut self) -> io::Result<Option<usize>> {
+        loop {
+            self.buf.clear();
+            let read = if self.compressed {
+                util::read_db_entry_include_newline(&mut self.reader, &mut self.buf)?
+            } else {
+                util::read_line_include_newline(&mut self.reader, &mut self.buf)?
+            };
+            if read == 0 {
+                // we have reached EOF
+                return Ok(None);
+            }
+            if let Some(b'\n') = self.buf.last() {
+                self.buf.pop();
+            }
+            if self.buf.is_empty() {
+                continue;
+            }
+            while self.section_remaining == 0 && self.section + 1 < self.section_sizes.len() {
+                self.section += 1;
+                self.section_remaining = self.section_sizes[self.section];
+            }
+            self.section_remaining = self.section_remaining.saturating_sub(1);
+
+            if self.compressed {
+                self.path = compress::decompress_line(&self.path, &self.buf);
+            } else {
+                mem::swap(&mut self.path, &mut self.buf);
+            }
+            return Ok(Some(self.section));
+        }
     }
 
-    let root = root.into();
-    if !root.is_dir() {
-        panic!("root is not a directory: {:?}", root);
+    /// Calls `f` with the path of every entry of the root with index `section` until `f` returns
+    /// `WalkStatus::Aborted`. The roots must be asked for in order, the entries of earlier roots
+    /// are skipped.
+    fn for_each_in_section<F>(&mut self, section: usize, mut f: F) -> io::Result<WalkStatus>
+    where
+        F: FnMut(&[u8]) -> WalkStatus,
+    {
+        loop {
+            if !self.pending && self.read_next()?.is_none() {
+                return Ok(WalkStatus::Ok);
+            }
+            if self.section > section {
+                self.pending = true;
+                return Ok(WalkStatus::Ok);
+            }
+            self.pending = false;
+            if self.section == section && f(&self.path) == WalkStatus::Aborted {
+                return Ok(WalkStatus::Aborted);
+            }
+        }
     }
-    let mut files = Vec::new();
-    let remove_root = if options.remove_root {
-        Some(root.as_ref())
+}
+
+/// The directory table is stored next to the database file. It lists every directory that was
+/// read when the database was built, together with its modification time, and is used by
+/// `update_database` to find the directories that have changed.
+fn dir_table_path(db_file: &Path) -> PathBuf {
+    let mut path = OsString::from(db_file.as_os_str());
+    path.push(".dirs");
+    PathBuf::from(path)
+}
+
+#[derive(Debug, PartialEq)]
+struct DirTable {
+    /// True if the database was built with `compress`, which the old entries are decoded with.
+    compressed: bool,
+    dirs: HashMap<PathBuf, u128>,
+}
+
+/// The directory table starts with a line that is `compressed` or `uncompressed`, followed by a
+/// line for each dir in the format `<mtime> <length> <path>`. The mtime is in nanoseconds since the
+/// Unix epoch and path is the path of the dir on the file system (including the scan root even if
+/// the database is built with `remove_root`). Length is the number of bytes in the path, since the
+/// path may contain newlines.
+fn write_dir_table(
+    table_file: &Path,
+    compressed: bool,
+    mut dirs: Vec<(PathBuf, u128)>,
+) -> io::Result<()> {
+    dirs.sort_unstable_by(|a, b| a.0.as_os_str().cmp(b.0.as_os_str()));
+    let mut table = BufWriter::new(File::create(table_file)?);
+    if compressed {
+        table.write_all(b"compressed\n")?;
     } else {
-        None
+        table.write_all(b"uncompressed\n")?;
+    }
+    for (path, mtime) in dirs {
+        let path = path.as_os_str().as_bytes();
+        write!(table, "{mtime} {} ", path.len())?;
+        table.write_all(path)?;
+        table.write_all(b"\n")?;
+    }
+    table.flush()
+}
+
+fn read_dir_table(table_file: &Path) -> io::Result<DirTable> {
+    let mut reader = BufReader::new(File::open(table_file)?);
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid directory table");
+    let mut line = Vec::new();
+    util::read_line(&mut reader, &mut line)?;
+    let compressed = match line.as_slice() {
+        b"compressed" => true,
+        b"uncompressed" => false,
+        _ => return Err(invalid()),
     };
-    let status = walk_dir_internal(
-        root.clone(),
-        remove_root,
-        options.mem_limit,
-        &on_flush,
-        &mut files,
-        &mut 0,
-    )?;
-    // flush any remaining file paths
-    if status == WalkStatus::Ok && !files.is_empty() {
-        on_flush(files);
-    }
-    Ok(())
+
+    let mut dirs = HashMap::new();
+    let mut field = Vec::new();
+    loop {
+        field.clear();
+        if reader.read_until(b' ', &mut field)? == 0 {
+            break;
+        }
+        let mtime = parse_field(&field).ok_or_else(invalid)?;
+        field.clear();
+        reader.read_until(b' ', &mut field)?;
+        let len: usize = parse_field(&field).ok_or_else(invalid)?;
+        // the path and its newline
+        let mut path = vec![0; len + 1];
+        reader
+            .read_exact(&mut path)
+            .map_err(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => invalid(),
+                _ => err,
+            })?;
+        if path.pop() != Some(b'\n') {
+            return Err(invalid());
+        }
+        dirs.insert(PathBuf::from(OsString::from_vec(path)), mtime);
+    }
+    Ok(DirTable { compressed, dirs })
+}
+
+/// Parses a number that is followed by a space.
+fn parse_field<T: std::str::FromStr>(field: &[u8]) -> Option<T> {
+    let field = field.strip_suffix(b" ")?;
+    std::str::from_utf8(field).ok()?.parse().ok()
 }
 
 #[cfg(test)]
+#[allow(
+    clippy::byte_char_slices,
+    clippy::useless_vec,
+    clippy::field_reassign_with_default
+)]
 mod tests {
-    use tempfile::TempDir;
     use super::*;
+    use tempfile::TempDir;
 
     #[test]
     fn test_build_database() {
@@ -318,7 +797,18 @@ mod tests {
         options.remove_root = true;
         build_database(&db_path, "tests/root", options).unwrap();
         assert!(db_path.is_file());
-        let content = fs::read(db_path).unwrap();
+        let mut reader = BufReader::new(File::open(db_path).unwrap());
+        let header = header::read_header(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            header.roots,
+            vec![RootTableEntry {
+                root: ScanRoot::new("tests/root"),
+                entries: 4,
+                root_removed: true,
+            }]
+        );
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
         // println!("{}", String::from_utf8_lossy(&content));
 
         let expected: Vec<u8> = vec![
@@ -338,4 +828,209 @@ mod tests {
 
         assert_eq!(content, expected);
     }
+
+    #[test]
+    fn test_update_database() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("root");
+        create_files(&root, &["a/1.txt", "a/b/2.txt", "c/3.txt"]);
+        let db_path = tmp_dir.path().join("database.anlocate");
+        build_database(&db_path, &root, DatabaseOptions::default()).unwrap();
+
+        create_files(&root, &["a/b/new.txt", "d/e/4.txt"]);
+        fs::remove_file(root.join("c/3.txt")).unwrap();
+        update_database(&db_path, &root, DatabaseOptions::default()).unwrap();
+
+        let rebuilt_path = tmp_dir.path().join("rebuilt.anlocate");
+        build_database(&rebuilt_path, &root, DatabaseOptions::default()).unwrap();
+        assert_eq!(
+            fs::read(&db_path).unwrap(),
+            fs::read(&rebuilt_path).unwrap()
+        );
+        assert_eq!(
+            read_dir_table(&dir_table_path(&db_path)).unwrap(),
+            read_dir_table(&dir_table_path(&rebuilt_path)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_database_reuses_unchanged_dirs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("root");
+        create_files(&root, &["a/1.txt", "a/2.txt", "b/3.txt"]);
+        let db_path = tmp_dir.path().join("database.anlocate");
+        let options = DatabaseOptions {
+            remove_root: true,
+            ..Default::default()
+        };
+        build_database(&db_path, &root, options.clone()).unwrap();
+
+        // remove a file without changing the mtime of its dir so that the dir looks unchanged
+        let dir_a = File::open(root.join("a")).unwrap();
+        let mtime = dir_a.metadata().unwrap().modified().unwrap();
+        fs::remove_file(root.join("a/1.txt")).unwrap();
+        dir_a.set_modified(mtime).unwrap();
+        // but b is changed
+        create_files(&root, &["b/4.txt"]);
+
+        update_database(&db_path, &root, options).unwrap();
+        // a/1.txt is still there since a was not re-read
+        assert_eq!(
+            database_paths(&db_path),
+            ["a/1.txt", "a/2.txt", "b/3.txt", "b/4.txt"].map(|path| (0, path.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_update_database_with_other_compression() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("root");
+        create_files(&root, &["a/1.txt", "a/2.txt"]);
+        let db_path = tmp_dir.path().join("database.anlocate");
+        build_database(&db_path, &root, DatabaseOptions::default()).unwrap();
+
+        // the old entries cannot be decoded as uncompressed ones, so the database is rebuilt
+        let options = DatabaseOptions {
+            compress: false,
+            ..Default::default()
+        };
+        update_database(&db_path, &root, options.clone()).unwrap();
+        let rebuilt_path = tmp_dir.path().join("rebuilt.anlocate");
+        build_database(&rebuilt_path, &root, options).unwrap();
+        assert_eq!(
+            fs::read(&db_path).unwrap(),
+            fs::read(&rebuilt_path).unwrap()
+        );
+        assert!(
+            !read_dir_table(&dir_table_path(&db_path))
+                .unwrap()
+                .compressed
+        );
+    }
+
+    #[test]
+    fn test_dir_table() {
+        let tmp_dir = TempDir::new().unwrap();
+        let table_path = tmp_dir.path().join("database.anlocate.dirs");
+        let dirs = vec![
+            (PathBuf::from("/root/a"), 1_700_000_000_123_456_789),
+            (PathBuf::from("/root/new\nline"), 0),
+            (PathBuf::from("/root/space and\ttab"), 42),
+        ];
+        write_dir_table(&table_path, true, dirs.clone()).unwrap();
+        assert_eq!(
+            read_dir_table(&table_path).unwrap(),
+            DirTable {
+                compressed: true,
+                dirs: dirs.into_iter().collect(),
+            }
+        );
+
+        for table in [
+            "12 7 /root/a\n",
+            "compressed\n12 6 /root/a\n",
+            "compressed\n12 9 /root/a\n",
+        ] {
+            fs::write(&table_path, table).unwrap();
+            let err = read_dir_table(&table_path).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_build_database_from_roots() {
+        let tmp_dir = TempDir::new().unwrap();
+        let internal = tmp_dir.path().join("internal");
+        let sdcard = tmp_dir.path().join("sdcard");
+        create_files(
+            &internal,
+            &[
+                "DCIM/a.jpg",
+                "Android/data/x.bin",
+                "Music/.thumbnails/t.jpg",
+                "Music/song.mp3",
+            ],
+        );
+        create_files(&sdcard, &["DCIM/b.jpg"]);
+        let roots = [
+            ScanRoot {
+                prune_paths: vec!["Android/data".into()],
+                prune_names: vec![".thumbnails".into()],
+                alias: Some("internal".to_string()),
+                ..ScanRoot::new(&internal)
+            },
+            ScanRoot {
+                alias: Some("sd".to_string()),
+                ..ScanRoot::new(&sdcard)
+            },
+        ];
+        let options = DatabaseOptions {
+            remove_root: true,
+            ..Default::default()
+        };
+        let db_path = tmp_dir.path().join("database.anlocate");
+        build_database_from_roots(&db_path, &roots, options.clone()).unwrap();
+
+        let mut reader = BufReader::new(File::open(&db_path).unwrap());
+        let header = header::read_header(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            header.roots,
+            vec![
+                RootTableEntry {
+                    root: roots[0].clone(),
+                    entries: 2,
+                    root_removed: true,
+                },
+                RootTableEntry {
+                    root: roots[1].clone(),
+                    entries: 1,
+                    root_removed: true,
+                },
+            ]
+        );
+        assert_eq!(
+            database_paths(&db_path),
+            vec![
+                (0, "DCIM/a.jpg".to_string()),
+                (0, "Music/song.mp3".to_string()),
+                (1, "DCIM/b.jpg".to_string()),
+            ]
+        );
+
+        // updating gives the same result as rebuilding
+        create_files(&sdcard, &["DCIM/c.jpg"]);
+        fs::remove_file(internal.join("DCIM/a.jpg")).unwrap();
+        update_database_from_roots(&db_path, &roots, options.clone()).unwrap();
+        let rebuilt_path = tmp_dir.path().join("rebuilt.anlocate");
+        build_database_from_roots(&rebuilt_path, &roots, options).unwrap();
+        assert_eq!(
+            fs::read(&db_path).unwrap(),
+            fs::read(&rebuilt_path).unwrap()
+        );
+        assert_eq!(
+            database_paths(&db_path),
+            vec![
+                (0, "Music/song.mp3".to_string()),
+                (1, "DCIM/b.jpg".to_string()),
+                (1, "DCIM/c.jpg".to_string()),
+            ]
+        );
+    }
+
+    fn database_paths(db_path: &Path) -> Vec<(usize, String)> {
+        let mut reader = DatabasePaths::open(db_path, true).unwrap();
+        let mut paths = Vec::new();
+        while let Some(root) = reader.read_next().unwrap() {
+            paths.push((root, String::from_utf8(reader.path.clone()).unwrap()));
+        }
+        paths
+    }
+
+    fn create_files(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+    }
 }
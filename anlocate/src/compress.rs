@@ -63,6 +63,43 @@ pub fn compress_line(result: &mut Vec<u8>, prev_line: &[u8], line: &[u8]) {
     push_line(result, common, substring);
 }
 
+/// Decompresses the forward-compressed `line` (without the end-of-line newline) given the
+/// (uncompressed) previous line `prev_line`.
+pub fn decompress_line(prev_line: &[u8], line: &[u8]) -> Vec<u8> {
+    let (data_start_index, common_len) = decode_count(line);
+
+    let common = &prev_line[0..common_len];
+    let current = &line[data_start_index..];
+
+    let mut result = Vec::with_capacity(common.len() + current.len());
+    result.extend_from_slice(common);
+    result.extend_from_slice(current);
+    result
+}
+
+/// Returns the number of bytes that the forward-compressed `line` shares with the previous line.
+pub fn common_len(line: &[u8]) -> usize {
+    decode_count(line).1
+}
+
+/// Returns the index where the data of the forward-compressed `line` starts and the number of
+/// bytes it shares with the previous line.
+#[rustfmt::skip]
+fn decode_count(line: &[u8]) -> (usize, usize) {
+    match line[0] {
+        // common len is 10 (ascii value of newline)
+        251 => (1, b'\n' as usize),
+        // common len is between 251-255
+        252 => (2, line[1] as usize),
+        // common len fits in 2-4 bytes
+        253 => (3, u32::from_le_bytes([line[1], line[2], 0, 0]) as usize),
+        254 => (4, u32::from_le_bytes([line[1], line[2], line[3], 0]) as usize),
+        255 => (5, u32::from_le_bytes([line[1], line[2], line[3], line[4]]) as usize),
+        // common len fits in the first byte
+        len => (1, len as usize),
+    }
+}
+
 fn push_line(result: &mut Vec<u8>, count: u32, line: &[u8]) {
     encode_count(result, count);
     for byte in line {
@@ -114,6 +151,7 @@ fn common_prefix_count(a: &[u8], b: &[u8]) -> u32 {
 }
 
 #[cfg(test)]
+#[allow(clippy::byte_char_slices)]
 mod tests {
     use super::*;
     use std::fs::File;
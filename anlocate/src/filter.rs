@@ -0,0 +1,237 @@
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime};
+
+/// A predicate on a field of an entry, parsed from a `field:value` query term such as `ext:pdf`,
+/// `type:file`, `size:>10M` or `modified:<7d`.
+///
+/// The database only stores the paths of files, so the filters other than `ext` stat the entry
+/// on the file system when searching. That is one `symlink_metadata` call for each entry that the
+/// rest of the search matches (the filters are matched last), so they are slow for broad queries.
+/// The entry is looked up by its path joined to its root if the root was removed, which needs the
+/// root table of the database, and an entry that can not be stat:ed never matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// The (lowercase) extension of the basename, e.g. `pdf` or `tar.gz`.
+    Extension(String),
+    Type(FileType),
+    /// The size in bytes.
+    Size(Comparison, u64),
+    /// The modification time compared to this time, e.g. `modified:<7d` is a modification time
+    /// that is greater than 7 days ago.
+    Modified(Comparison, SystemTime),
+}
+
+/// The type of an entry. Directories are not stored in the database so there is no type for them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    File,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn is_match<T: PartialOrd>(self, value: T, operand: T) -> bool {
+        match self {
+            Comparison::Less => value < operand,
+            Comparison::LessOrEqual => value <= operand,
+            Comparison::Equal => value == operand,
+            Comparison::GreaterOrEqual => value >= operand,
+            Comparison::Greater => value > operand,
+        }
+    }
+
+    /// The comparison with the operands swapped, e.g. `a < b` is `b > a`.
+    fn reversed(self) -> Comparison {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+
+    /// Splits the comparison operator from the start of `value`. No operator is `Equal`.
+    fn parse(value: &str) -> (Comparison, &str) {
+        let operators = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ];
+        for (operator, comparison) in operators {
+            if let Some(rest) = value.strip_prefix(operator) {
+                return (comparison, rest);
+            }
+        }
+        (Comparison::Equal, value)
+    }
+}
+
+impl Filter {
+    /// Parses the value of the filter `field`. Returns None if the field is unknown or the value
+    /// is invalid.
+    ///
+    /// * `ext:pdf`
+    /// * `type:file` or `type:link` (or `f`, `l`)
+    /// * `size:>10M` with the units `K`, `M` and `G` (powers of 1024), optionally followed by `B`
+    /// * `modified:<7d` with the units `s`, `m` (minutes), `h`, `d`, `w` and `y`
+    ///
+    /// Sizes and modification times can be compared with `<`, `<=`, `>`, `>=` and `=` (which is
+    /// the default for sizes).
+    pub fn parse(field: &str, value: &str) -> Option<Filter> {
+        match field {
+            "ext" => {
+                let ext = value.strip_prefix('.').unwrap_or(value);
+                (!ext.is_empty()).then(|| Filter::Extension(ext.to_lowercase()))
+            }
+            "type" => match value {
+                "file" | "f" => Some(Filter::Type(FileType::File)),
+                "link" | "l" => Some(Filter::Type(FileType::Symlink)),
+                _ => None,
+            },
+            "size" => {
+                let (comparison, size) = Comparison::parse(value);
+                Some(Filter::Size(comparison, parse_size(size)?))
+            }
+            "modified" => {
+                let (comparison, age) = Comparison::parse(value);
+                if comparison == Comparison::Equal {
+                    // an exact age is not useful
+                    return None;
+                }
+                let time = SystemTime::now().checked_sub(parse_age(age)?)?;
+                // an older age is an earlier time
+                Some(Filter::Modified(comparison.reversed(), time))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if the filter needs the metadata of the entry.
+    pub fn needs_metadata(&self) -> bool {
+        !matches!(self, Filter::Extension(_))
+    }
+
+    /// Returns true if the entry at `path` matches. `metadata` is the metadata of the entry (not
+    /// following symlinks) if the filter needs it, and a filter never matches without it.
+    pub fn is_match(&self, path: &str, metadata: Option<&Metadata>) -> bool {
+        match (self, metadata) {
+            (Filter::Extension(ext), _) => has_extension(path, ext),
+            (_, None) => false,
+            (Filter::Type(FileType::File), Some(metadata)) => metadata.is_file(),
+            (Filter::Type(FileType::Symlink), Some(metadata)) => metadata.is_symlink(),
+            (Filter::Size(comparison, size), Some(metadata)) => {
+                comparison.is_match(metadata.len(), *size)
+            }
+            (Filter::Modified(comparison, time), Some(metadata)) => metadata
+                .modified()
+                .is_ok_and(|modified| comparison.is_match(modified, *time)),
+        }
+    }
+}
+
+/// Returns true if the basename of `path` ends with `.` and the lowercase `ext`, after at least
+/// one other char.
+fn has_extension(path: &str, ext: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    name.strip_suffix(ext)
+        .and_then(|stem| stem.strip_suffix('.'))
+        .is_some_and(|stem| !stem.is_empty())
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.strip_suffix(['B', 'b']).unwrap_or(size);
+    let (number, unit) = match size.char_indices().last()? {
+        (i, 'K' | 'k') => (&size[..i], 1 << 10),
+        (i, 'M' | 'm') => (&size[..i], 1 << 20),
+        (i, 'G' | 'g') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn parse_age(age: &str) -> Option<Duration> {
+    let (i, unit) = age.char_indices().last()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        'y' => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let number: u64 = age[..i].parse().ok()?;
+    Some(Duration::from_secs(number.checked_mul(seconds)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_filter() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("Report.PDF");
+        fs::write(&file, vec![0; 2048]).unwrap();
+        let metadata = fs::symlink_metadata(&file).unwrap();
+        let link = dir.path().join("link.pdf");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        let link_metadata = fs::symlink_metadata(&link).unwrap();
+        let is_match = |filter: &str, metadata| {
+            let (field, value) = filter.split_once(':').unwrap();
+            Filter::parse(field, value)
+                .unwrap()
+                .is_match("docs/Report.PDF", metadata)
+        };
+
+        assert!(is_match("ext:pdf", None));
+        assert!(is_match("ext:.pdf", None));
+        assert!(!is_match("ext:df", None));
+        assert!(Filter::parse("ext", "gz")
+            .unwrap()
+            .is_match("a/b.tar.gz", None));
+        assert!(!Filter::parse("ext", "pdf").unwrap().is_match(".pdf", None));
+
+        assert!(is_match("type:file", Some(&metadata)));
+        assert!(!is_match("type:link", Some(&metadata)));
+        assert!(is_match("type:l", Some(&link_metadata)));
+        assert!(!is_match("type:f", Some(&link_metadata)));
+        assert!(!is_match("type:file", None));
+
+        assert!(is_match("size:2K", Some(&metadata)));
+        assert!(is_match("size:>1k", Some(&metadata)));
+        assert!(is_match("size:<=2KB", Some(&metadata)));
+        assert!(!is_match("size:>2048", Some(&metadata)));
+        assert!(!is_match("size:>=1M", Some(&metadata)));
+
+        assert!(is_match("modified:<1h", Some(&metadata)));
+        assert!(!is_match("modified:>1d", Some(&metadata)));
+
+        for (field, value) in [
+            ("size", ">10X"),
+            ("size", "big"),
+            ("modified", "7d"),
+            ("modified", "<7"),
+            ("type", "pipe"),
+            // dirs are not in the database
+            ("type", "dir"),
+            ("ext", ""),
+            ("name", "a"),
+        ] {
+            assert_eq!(Filter::parse(field, value), None, "{field}:{value}");
+        }
+    }
+}
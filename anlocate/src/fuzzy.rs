@@ -0,0 +1,250 @@
+/// An fzf-style fuzzy pattern, case-insensitive unless created with [`FuzzyPattern::with_case`].
+/// The pattern matches a text if its characters occur in the text in the same order, and the match
+/// is scored so that better matches can be ranked higher. Characters at the start of a word, after
+/// a path separator, at a camelCase transition or directly following the previous match give a
+/// bonus, and gaps between matched characters give a penalty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyPattern {
+    chars: Vec<char>,
+    case_sensitive: bool,
+}
+
+const SCORE_MATCH: i32 = 16;
+const PENALTY_GAP_START: i32 = 3;
+const PENALTY_GAP_EXTENSION: i32 = 1;
+/// The match is right after a path separator.
+const BONUS_PATH_SEPARATOR: i32 = 9;
+/// The match is at the start of a word (after a space, `_`, `-`, `.` or at the start of the text).
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_CAMEL_CASE: i32 = 7;
+const BONUS_CONSECUTIVE: i32 = 4;
+/// The bonus of the first character of the pattern is multiplied by this.
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharClass {
+    Lower,
+    Upper,
+    Digit,
+    PathSeparator,
+    Separator,
+    Other,
+}
+
+impl CharClass {
+    fn of(c: char) -> CharClass {
+        match c {
+            '/' => CharClass::PathSeparator,
+            ' ' | '_' | '-' | '.' => CharClass::Separator,
+            c if c.is_numeric() => CharClass::Digit,
+            c if c.is_uppercase() => CharClass::Upper,
+            c if c.is_alphabetic() => CharClass::Lower,
+            _ => CharClass::Other,
+        }
+    }
+}
+
+impl FuzzyPattern {
+    pub fn new(pattern: &str) -> FuzzyPattern {
+        FuzzyPattern::with_case(pattern, false)
+    }
+
+    pub fn with_case(pattern: &str, case_sensitive: bool) -> FuzzyPattern {
+        FuzzyPattern {
+            chars: pattern
+                .chars()
+                .map(|c| fold_char(c, case_sensitive))
+                .collect(),
+            case_sensitive,
+        }
+    }
+
+    /// Returns the char that is compared to the pattern.
+    fn fold(&self, c: char) -> char {
+        fold_char(c, self.case_sensitive)
+    }
+
+    /// Returns the score of the best match in `text`, or None if the pattern does not match.
+    pub fn score(&self, text: &str) -> Option<i32> {
+        if self.chars.is_empty() {
+            return Some(0);
+        }
+        if !self.is_subsequence_of(text) {
+            return None;
+        }
+        let (folded, bonus) = self.classify(text);
+        let mut scores = self.first_row(&folded, &bonus);
+        let mut next = vec![None; folded.len()];
+        for &p in &self.chars[1..] {
+            self.next_row(p, &folded, &bonus, &scores, &mut next);
+            std::mem::swap(&mut scores, &mut next);
+        }
+        scores.into_iter().flatten().max()
+    }
+
+    /// Returns the byte indexes in `text` of the chars of the best match, or None if the pattern
+    /// does not match.
+    pub fn positions(&self, text: &str) -> Option<Vec<usize>> {
+        if self.chars.is_empty() {
+            return Some(Vec::new());
+        }
+        if !self.is_subsequence_of(text) {
+            return None;
+        }
+        let (folded, bonus) = self.classify(text);
+        let mut rows = vec![self.first_row(&folded, &bonus)];
+        for &p in &self.chars[1..] {
+            let mut next = vec![None; folded.len()];
+            self.next_row(p, &folded, &bonus, &rows[rows.len() - 1], &mut next);
+            rows.push(next);
+        }
+
+        // walk back from the last char of the best match to the matches it was scored from
+        let (mut j, mut score) = rows[rows.len() - 1]
+            .iter()
+            .enumerate()
+            .filter_map(|(j, score)| Some((j, (*score)?)))
+            .max_by_key(|&(_, score)| score)?;
+        let mut positions = vec![j];
+        for row in rows[..rows.len() - 1].iter().rev() {
+            let consecutive =
+                row[j - 1].map(|prev| prev + SCORE_MATCH + bonus[j].max(BONUS_CONSECUTIVE));
+            let prev = if consecutive == Some(score) {
+                j - 1
+            } else {
+                (0..j - 1).rev().find(|&i| {
+                    let gap = PENALTY_GAP_START + PENALTY_GAP_EXTENSION * (j - 2 - i) as i32;
+                    row[i].map(|prev| prev - gap + SCORE_MATCH + bonus[j]) == Some(score)
+                })?
+            };
+            score = row[prev]?;
+            j = prev;
+            positions.push(j);
+        }
+        positions.reverse();
+        let byte_indexes: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        Some(positions.into_iter().map(|j| byte_indexes[j]).collect())
+    }
+
+    /// A quick check that the pattern is a subsequence of `text` before the expensive scoring.
+    fn is_subsequence_of(&self, text: &str) -> bool {
+        let mut pattern = self.chars.iter().peekable();
+        for c in text.chars() {
+            pattern.next_if_eq(&&self.fold(c));
+        }
+        pattern.peek().is_none()
+    }
+
+    /// Returns the folded chars of `text` and the bonus of matching each of them.
+    fn classify(&self, text: &str) -> (Vec<char>, Vec<i32>) {
+        let mut folded = Vec::with_capacity(text.len());
+        let mut bonus = Vec::with_capacity(text.len());
+        let mut prev_class = CharClass::Separator;
+        for c in text.chars() {
+            let class = CharClass::of(c);
+            bonus.push(match (prev_class, class) {
+                (CharClass::PathSeparator, _) => BONUS_PATH_SEPARATOR,
+                (CharClass::Separator, CharClass::Separator) => 0,
+                (CharClass::Separator, _) => BONUS_BOUNDARY,
+                (CharClass::Lower, CharClass::Upper) => BONUS_CAMEL_CASE,
+                (CharClass::Lower | CharClass::Upper, CharClass::Digit) => BONUS_CAMEL_CASE,
+                _ => 0,
+            });
+            folded.push(self.fold(c));
+            prev_class = class;
+        }
+        (folded, bonus)
+    }
+
+    /// The scores of matching the first pattern char at each position of the text, or None if it
+    /// cannot be matched there.
+    fn first_row(&self, folded: &[char], bonus: &[i32]) -> Vec<Option<i32>> {
+        folded
+            .iter()
+            .zip(bonus)
+            .map(|(&c, &bonus)| {
+                (c == self.chars[0]).then_some(SCORE_MATCH + bonus * BONUS_FIRST_CHAR_MULTIPLIER)
+            })
+            .collect()
+    }
+
+    /// `scores[j]` is the best score of the pattern chars so far with the last one matched at
+    /// `folded[j]`. Sets `next` to the same for the pattern chars followed by `p`.
+    fn next_row(
+        &self,
+        p: char,
+        folded: &[char],
+        bonus: &[i32],
+        scores: &[Option<i32>],
+        next: &mut [Option<i32>],
+    ) {
+        // the best score of a previous match followed by a gap up to the current position
+        let mut gap: Option<i32> = None;
+        for j in 0..folded.len() {
+            next[j] = None;
+            if j == 0 {
+                continue;
+            }
+            if folded[j] == p {
+                let consecutive = scores[j - 1]
+                    .map(|score| score + SCORE_MATCH + bonus[j].max(BONUS_CONSECUTIVE));
+                let after_gap = gap.map(|score| score + SCORE_MATCH + bonus[j]);
+                next[j] = consecutive.max(after_gap);
+            }
+            gap = gap
+                .map(|score| score - PENALTY_GAP_EXTENSION)
+                .max(scores[j - 1].map(|score| score - PENALTY_GAP_START));
+        }
+    }
+}
+
+/// Folds a char of the pattern or the text, so that both are folded the same way.
+fn fold_char(c: char, case_sensitive: bool) -> char {
+    if case_sensitive {
+        c
+    } else {
+        // chars that lowercase to several chars are rare in paths, use the first one
+        c.to_lowercase().next().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        let score = |pattern: &str, text: &str| FuzzyPattern::new(pattern).score(text);
+
+        assert!(score("scrnsht2023", "Screenshots/Screenshot_20230101.png").is_some());
+        assert_eq!(
+            score("scrnsht2023", "Screenshots/Screenshot_2022.png"),
+            None
+        );
+        assert_eq!(score("ba", "abc"), None);
+        assert_eq!(score("", "abc"), Some(0));
+        // İ lowercases to two chars
+        assert!(score("İstanbul", "Photos/İstanbul.jpg").is_some());
+        assert!(score("istanbul", "Photos/İSTANBUL.jpg").is_some());
+
+        // word starts, path separators and camelCase beat matches inside words
+        assert!(score("dc", "DCIM/camera") > score("dc", "abdcef"));
+        assert!(score("ca", "DCIM/camera") > score("ca", "DCIM/xcax"));
+        assert!(score("mf", "myFile.txt") > score("mf", "mxfile.txt"));
+        // consecutive matches beat matches with gaps
+        assert!(score("abc", "xabcx") > score("abc", "xaxbxcx"));
+        // shorter gaps beat longer gaps
+        assert!(score("ac", "xabcx") > score("ac", "xabbbbcx"));
+
+        let positions = |pattern: &str, text: &str| FuzzyPattern::new(pattern).positions(text);
+        assert_eq!(positions("dc", "DCIM/camera"), Some(vec![0, 1]));
+        assert_eq!(positions("ca", "xcax/camera"), Some(vec![5, 6]));
+        assert_eq!(positions("mf", "myFile.txt"), Some(vec![0, 2]));
+        assert_eq!(positions("äb", "xäxb"), Some(vec![1, 4]));
+        assert_eq!(positions("ba", "abc"), None);
+
+        let pattern = FuzzyPattern::with_case("sS", true);
+        assert!(pattern.score("screenShot").is_some());
+        assert!(pattern.score("screenshot").is_none());
+    }
+}
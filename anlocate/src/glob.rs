@@ -0,0 +1,221 @@
+/// A glob pattern, case-insensitive unless created with [`Glob::with_case`].
+///
+/// * `?` matches any character except `/`
+/// * `*` matches any number of characters except `/`
+/// * `**` matches any number of characters, and `**/` also matches zero directories
+/// * `[abc]`, `[a-z]` match one of the characters in the class, `[!abc]` or `[^abc]` one that is
+///   not in it
+/// * `\` escapes the next character
+///
+/// The pattern must match the whole text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+    case_sensitive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    Star,
+    DoubleStar,
+    DoubleStarSlash,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        Glob::with_case(pattern, false)
+    }
+
+    pub fn with_case(pattern: &str, case_sensitive: bool) -> Glob {
+        let push_char = |tokens: &mut Vec<Token>, c: char| {
+            if case_sensitive {
+                tokens.push(Token::Char(c));
+            } else {
+                tokens.extend(c.to_lowercase().map(Token::Char));
+            }
+        };
+        let pattern: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < pattern.len() {
+            match pattern[i] {
+                '\\' if i + 1 < pattern.len() => {
+                    i += 1;
+                    push_char(&mut tokens, pattern[i]);
+                }
+                '?' => tokens.push(Token::AnyChar),
+                '*' if pattern.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    if pattern.get(i + 1) == Some(&'/') {
+                        i += 1;
+                        tokens.push(Token::DoubleStarSlash);
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                    }
+                }
+                '*' => tokens.push(Token::Star),
+                '[' => match parse_class(&pattern[i + 1..], case_sensitive) {
+                    Some((class, len)) => {
+                        tokens.push(class);
+                        i += len;
+                    }
+                    // an unclosed [ is matched literally
+                    None => tokens.push(Token::Char('[')),
+                },
+                c => push_char(&mut tokens, c),
+            }
+            i += 1;
+        }
+        Glob {
+            tokens,
+            case_sensitive,
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = if self.case_sensitive {
+            text.chars().collect()
+        } else if text.is_ascii() {
+            text.chars().map(|c| c.to_ascii_lowercase()).collect()
+        } else {
+            text.chars().flat_map(char::to_lowercase).collect()
+        };
+
+        // matched[i] is true if the tokens so far match the first i chars of the text
+        let mut matched = vec![false; text.len() + 1];
+        matched[0] = true;
+        let mut next = vec![false; text.len() + 1];
+        for token in &self.tokens {
+            next[0] = false;
+            match token {
+                Token::Char(_) | Token::AnyChar | Token::Class { .. } => {
+                    for i in 0..text.len() {
+                        next[i + 1] = matched[i] && token_matches(token, text[i]);
+                    }
+                }
+                Token::Star => {
+                    next[0] = matched[0];
+                    for i in 0..text.len() {
+                        next[i + 1] = matched[i + 1] || (next[i] && text[i] != '/');
+                    }
+                }
+                Token::DoubleStar => {
+                    next[0] = matched[0];
+                    for i in 0..text.len() {
+                        next[i + 1] = matched[i + 1] || next[i];
+                    }
+                }
+                Token::DoubleStarSlash => {
+                    next[0] = matched[0];
+                    let mut any_matched = matched[0];
+                    for i in 0..text.len() {
+                        next[i + 1] = matched[i + 1] || (any_matched && text[i] == '/');
+                        any_matched |= matched[i + 1];
+                    }
+                }
+            }
+            if !next.contains(&true) {
+                return false;
+            }
+            std::mem::swap(&mut matched, &mut next);
+        }
+        matched[text.len()]
+    }
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn token_matches(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(expected) => c == *expected,
+        Token::AnyChar => c != '/',
+        Token::Class { negated, ranges } => {
+            c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+        }
+        _ => false,
+    }
+}
+
+/// Parses the character class after a `[`. Returns the class and the number of chars it used
+/// (including the closing `]`), or None if the class is not closed.
+fn parse_class(pattern: &[char], case_sensitive: bool) -> Option<(Token, usize)> {
+    let fold = |c| if case_sensitive { c } else { lowercase(c) };
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let start = i;
+    loop {
+        let mut c = *pattern.get(i)?;
+        // a ] first in the class is a literal
+        if c == ']' && i > start {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        if c == '\\' {
+            i += 1;
+            c = *pattern.get(i)?;
+        }
+        let lo = fold(c);
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&c| c != ']') {
+            let hi = fold(pattern[i + 2]);
+            ranges.push((lo, hi));
+            i += 3;
+        } else {
+            ranges.push((lo, lo));
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let matches = |pattern: &str, text: &str| Glob::new(pattern).is_match(text);
+
+        assert!(matches("*.jpg", "IMG_1234.JPG"));
+        assert!(!matches("*.jpg", "DCIM/IMG_1234.jpg"));
+        assert!(!matches("*.jpg", "IMG_1234.jpg.txt"));
+        assert!(matches("IMG_2023*", "img_20230101.png"));
+        assert!(matches("IMG_????.jpg", "IMG_1234.jpg"));
+        assert!(!matches("IMG_????.jpg", "IMG_12345.jpg"));
+
+        assert!(matches("**/DCIM/**/*.mp4", "DCIM/a.mp4"));
+        assert!(matches(
+            "**/DCIM/**/*.mp4",
+            "/storage/emulated/0/DCIM/Camera/2023/a.mp4"
+        ));
+        assert!(!matches(
+            "**/DCIM/**/*.mp4",
+            "/storage/emulated/0/DCIMx/a.mp4"
+        ));
+        assert!(!matches("**/DCIM/**/*.mp4", "DCIM/a.mp4/b.txt"));
+        assert!(matches("/storage/**", "/storage/emulated/0/a.txt"));
+
+        assert!(matches("[a-c]?[!0-9].txt", "Bxy.txt"));
+        assert!(!matches("[a-c]?[!0-9].txt", "dxy.txt"));
+        assert!(!matches("[a-c]?[^0-9].txt", "bx1.txt"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("a[b", "A[B"));
+        assert!(matches(r"\*.txt", "*.txt"));
+        assert!(!matches(r"\*.txt", "a.txt"));
+        assert!(matches("å*ö", "Åäö"));
+
+        let glob = Glob::with_case("IMG_[A-Z]*.jpg", true);
+        assert!(glob.is_match("IMG_A1.jpg"));
+        assert!(!glob.is_match("img_A1.jpg"));
+        assert!(!glob.is_match("IMG_a1.jpg"));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::build::ScanRoot;
+use crate::util;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+/// Databases with a header start with this line. A database without a header consists of
+/// compressed entries only, and since the first entry has no common prefix with a previous entry
+/// its first byte is always 0.
+const MAGIC: &[u8] = b"\xFFanlocate\n";
+
+/// The database header holds the root table. After the header the entries of each root follow
+/// each other, in the same order as in the root table.
+///
+/// The header is stored as lines of tab separated fields, ending with an empty line:
+///
+/// ```text
+/// root <entries> <path>
+/// root-removed
+/// alias <alias>
+/// prune-path <path>
+/// prune-name <name>
+/// ```
+///
+/// The alias and prune lines belong to the closest preceding root line.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DatabaseHeader {
+    pub roots: Vec<RootTableEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootTableEntry {
+    pub root: ScanRoot,
+    /// The number of entries in the database that belong to this root.
+    pub entries: u64,
+    /// True if the entries are stored relative to the root (the database was built with
+    /// `remove_root`).
+    pub root_removed: bool,
+}
+
+/// Reads the header if the database has one, otherwise nothing is consumed from the reader.
+pub fn read_header(reader: &mut BufReader<impl Read>) -> io::Result<Option<DatabaseHeader>> {
+    if !reader.fill_buf()?.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    reader.consume(MAGIC.len());
+
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid database header");
+    let mut header = DatabaseHeader::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if util::read_line(reader, &mut line)? == 0 {
+            return Err(invalid());
+        }
+        if line.is_empty() {
+            // end of header
+            return Ok(Some(header));
+        }
+
+        let (key, value) = match line.iter().position(|&b| b == b'\t') {
+            Some(tab) => (&line[..tab], &line[tab + 1..]),
+            None => (line.as_slice(), &[][..]),
+        };
+        if key == b"root" {
+            let tab = value.iter().position(|&b| b == b'\t').ok_or_else(invalid)?;
+            let entries = std::str::from_utf8(&value[..tab])
+                .ok()
+                .and_then(|entries| entries.parse().ok())
+                .ok_or_else(invalid)?;
+            let path = PathBuf::from(OsStr::from_bytes(&value[tab + 1..]));
+            header.roots.push(RootTableEntry {
+                root: ScanRoot::new(path),
+                entries,
+                root_removed: false,
+            });
+            continue;
+        }
+
+        let Some(RootTableEntry {
+            root, root_removed, ..
+        }) = header.roots.last_mut()
+        else {
+            return Err(invalid());
+        };
+        match key {
+            b"root-removed" => *root_removed = true,
+            b"alias" => {
+                let alias = String::from_utf8(value.to_vec()).map_err(|_| invalid())?;
+                root.alias = Some(alias);
+            }
+            b"prune-path" => root
+                .prune_paths
+                .push(PathBuf::from(OsStr::from_bytes(value))),
+            b"prune-name" => root
+                .prune_names
+                .push(OsString::from(OsStr::from_bytes(value))),
+            // ignore unknown keys so that fields can be added without breaking old readers
+            _ => {}
+        }
+    }
+}
+
+pub fn write_header(writer: &mut impl Write, header: &DatabaseHeader) -> io::Result<()> {
+    fn write_field(writer: &mut impl Write, key: &str, value: &[u8]) -> io::Result<()> {
+        if value.contains(&b'\n') {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "header fields cannot contain newlines",
+            ));
+        }
+        writer.write_all(key.as_bytes())?;
+        writer.write_all(b"\t")?;
+        writer.write_all(value)?;
+        writer.write_all(b"\n")
+    }
+
+    writer.write_all(MAGIC)?;
+    for RootTableEntry {
+        root,
+        entries,
+        root_removed,
+    } in &header.roots
+    {
+        let mut value = format!("{entries}\t").into_bytes();
+        value.extend_from_slice(root.path.as_os_str().as_bytes());
+        write_field(writer, "root", &value)?;
+        if *root_removed {
+            write_field(writer, "root-removed", &[])?;
+        }
+        if let Some(alias) = &root.alias {
+            write_field(writer, "alias", alias.as_bytes())?;
+        }
+        for path in &root.prune_paths {
+            write_field(writer, "prune-path", path.as_os_str().as_bytes())?;
+        }
+        for name in &root.prune_names {
+            write_field(writer, "prune-name", name.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let header = DatabaseHeader {
+            roots: vec![
+                RootTableEntry {
+                    root: ScanRoot {
+                        path: "/storage/emulated/0".into(),
+                        prune_paths: vec!["Android/data".into()],
+                        prune_names: vec![".thumbnails".into(), ".git".into()],
+                        alias: Some("internal".to_string()),
+                    },
+                    entries: 1234,
+                    root_removed: true,
+                },
+                RootTableEntry {
+                    root: ScanRoot::new("/storage/sd card"),
+                    entries: 0,
+                    root_removed: false,
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, &header).unwrap();
+        bytes.extend_from_slice(&[0, b'/', b'a', b'\n']);
+
+        let mut reader = BufReader::new(bytes.as_slice());
+        assert_eq!(read_header(&mut reader).unwrap(), Some(header));
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [0, b'/', b'a', b'\n']);
+
+        // databases without a header are left untouched
+        let mut reader = BufReader::new(rest.as_slice());
+        assert_eq!(read_header(&mut reader).unwrap(), None);
+        assert_eq!(reader.fill_buf().unwrap(), [0, b'/', b'a', b'\n']);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{header, util};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// A change to the file system that happened after the database was built.
+/// `root` is the index of the root that the path belongs to in the root table (0 if the database
+/// has no header). The path must be given in the same form as it is stored in the database, i.e.
+/// without the scan root if the database was built with `remove_root`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    Add {
+        root: usize,
+        path: String,
+    },
+    Remove {
+        root: usize,
+        path: String,
+    },
+    /// Removes all paths under the directory.
+    RemoveDir {
+        root: usize,
+        path: String,
+    },
+}
+
+/// The paths that have been added and removed since the database was built, as recorded in the
+/// journal file next to the database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Journal {
+    /// The changes by the index of the root they were made in.
+    roots: BTreeMap<usize, RootChanges>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct RootChanges {
+    added: BTreeSet<String>,
+    removed: HashSet<String>,
+    removed_dirs: HashSet<String>,
+}
+
+impl Journal {
+    /// Loads the journal of the database `db_file`. If nothing has been recorded since the
+    /// database was built the journal is empty.
+    pub fn load<P: AsRef<Path>>(db_file: P) -> io::Result<Journal> {
+        match File::open(journal_path(db_file.as_ref())) {
+            Ok(file) => Journal::read(&mut BufReader::new(file)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Journal::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads journal entries from `reader` and applies them in order. Each entry is a line in the
+    /// format `<op><root>\t<path>`, where op is `+` or `-`.
+    pub fn read(reader: &mut BufReader<impl Read>) -> io::Result<Journal> {
+        let mut journal = Journal::default();
+        let mut line = Vec::new();
+        while util::read_line(reader, &mut line)? != 0 {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid journal entry");
+            let tab = line.iter().position(|&b| b == b'\t').ok_or_else(invalid)?;
+            let root = std::str::from_utf8(&line[1..tab])
+                .ok()
+                .and_then(|root| root.parse().ok())
+                .ok_or_else(invalid)?;
+            let Ok(path) = std::str::from_utf8(&line[tab + 1..]) else {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid journal path",
+                ));
+            };
+            let path = path.to_string();
+            match line[0] {
+                b'+' => journal.apply(JournalEntry::Add { root, path }),
+                // removed dirs are stored with a trailing slash
+                b'-' if path.ends_with('/') => {
+                    let path = path[..path.len() - 1].to_string();
+                    journal.apply(JournalEntry::RemoveDir { root, path })
+                }
+                b'-' => journal.apply(JournalEntry::Remove { root, path }),
+                _ => return Err(invalid()),
+            }
+            line.clear();
+        }
+        Ok(journal)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.values().all(|changes| {
+            changes.added.is_empty()
+                && changes.removed.is_empty()
+                && changes.removed_dirs.is_empty()
+        })
+    }
+
+    fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Add { root, path } => {
+                let changes = self.roots.entry(root).or_default();
+                changes.removed.remove(&path);
+                changes.added.insert(path);
+            }
+            JournalEntry::Remove { root, path } => {
+                let changes = self.roots.entry(root).or_default();
+                changes.added.remove(&path);
+                changes.removed.insert(path);
+            }
+            JournalEntry::RemoveDir { root, path } => {
+                let changes = self.roots.entry(root).or_default();
+                changes.added.retain(|added| !is_in_dir(added, &path));
+                changes.removed_dirs.insert(path);
+            }
+        }
+    }
+
+    /// The added paths with the index of their root, sorted by root and path.
+    pub(crate) fn added(&self) -> impl Iterator<Item = (usize, &String)> {
+        self.roots
+            .iter()
+            .flat_map(|(&root, changes)| changes.added.iter().map(move |path| (root, path)))
+    }
+
+    /// Returns true if the journal overrides the database entry for `path` in `root`, in which
+    /// case the entry should be ignored. Added paths are overridden as well so that they are not
+    /// reported twice if they were already in the database.
+    pub(crate) fn overrides(&self, root: usize, path: &str) -> bool {
+        let Some(changes) = self.roots.get(&root) else {
+            return false;
+        };
+        changes.removed.contains(path)
+            || changes.added.contains(path)
+            || (!changes.removed_dirs.is_empty()
+                && path
+                    .match_indices('/')
+                    .any(|(i, _)| changes.removed_dirs.contains(&path[..i])))
+    }
+}
+
+fn is_in_dir(path: &str, dir: &str) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/'
+}
+
+/// Appends `entries` to the journal of the database `db_file`.
+pub fn record<P: AsRef<Path>>(db_file: P, entries: &[JournalEntry]) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        let (op, root, path) = match entry {
+            JournalEntry::Add { root, path } => (b'+', root, path),
+            JournalEntry::Remove { root, path } | JournalEntry::RemoveDir { root, path } => {
+                (b'-', root, path)
+            }
+        };
+        if path.contains('\n') {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "journal paths cannot contain newlines",
+            ));
+        }
+        buf.push(op);
+        buf.extend_from_slice(format!("{root}\t{path}").as_bytes());
+        if let JournalEntry::RemoveDir { .. } = entry {
+            buf.push(b'/');
+        }
+        buf.push(b'\n');
+    }
+    // the entries are written with a single write so that concurrent writers do not interleave
+    let path = journal_path(db_file.as_ref());
+    loop {
+        let mut journal = OpenOptions::new().create(true).append(true).open(&path)?;
+        // writers share the lock, `discard_recorded` takes it exclusively while it replaces the
+        // journal. The lock is released when the file is closed.
+        journal.lock_shared()?;
+        if is_journal(&journal, &path)? {
+            return journal.write_all(&buf);
+        }
+    }
+}
+
+/// Returns true if the open `journal` is still the file at `path`, and not one that
+/// `discard_recorded` replaced or removed before it was locked.
+fn is_journal(journal: &File, path: &Path) -> io::Result<bool> {
+    let opened = journal.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Records that the file `path` was added. The path is the full path of the file, which is made
+/// relative to its root if the database was built with `remove_root`.
+pub fn record_add<P: AsRef<Path>>(db_file: P, path: &str) -> io::Result<()> {
+    let db_file = db_file.as_ref();
+    let (root, path) = stored_path(db_file, path)?;
+    record(db_file, &[JournalEntry::Add { root, path }])
+}
+
+/// Records that the file `path` was removed. The path is given as for `record_add`.
+pub fn record_remove<P: AsRef<Path>>(db_file: P, path: &str) -> io::Result<()> {
+    let db_file = db_file.as_ref();
+    let (root, path) = stored_path(db_file, path)?;
+    record(db_file, &[JournalEntry::Remove { root, path }])
+}
+
+/// Returns the index of the root that `path` belongs to in the root table of the database, which
+/// is the first root that is a prefix of it, and the path as it is stored in the database.
+fn stored_path(db_file: &Path, path: &str) -> io::Result<(usize, String)> {
+    let header = match File::open(db_file) {
+        Ok(file) => header::read_header(&mut BufReader::new(file))?,
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    // a database without a header only has one root, which is not removed
+    let Some(header) = header else {
+        return Ok((0, path.to_string()));
+    };
+    for (i, entry) in header.roots.iter().enumerate() {
+        let Ok(relative) = Path::new(path).strip_prefix(&entry.root.path) else {
+            continue;
+        };
+        let path = match relative.to_str() {
+            Some(relative) if entry.root_removed => relative,
+            _ => path,
+        };
+        return Ok((i, path.to_string()));
+    }
+    Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        "the path is not in a root of the database",
+    ))
+}
+
+/// Returns the number of bytes recorded in the journal of `db_file`.
+pub(crate) fn recorded_len(db_file: &Path) -> io::Result<u64> {
+    match fs::metadata(journal_path(db_file)) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the first `len` bytes of the journal of `db_file`. This is the compaction step: it is
+/// called after the database has been rebuilt with the changes that were recorded in those bytes.
+/// Entries recorded while the database was being built are kept.
+pub(crate) fn discard_recorded(db_file: &Path, len: u64) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let path = journal_path(db_file);
+    let mut journal = File::open(&path)?;
+    // entries cannot be recorded until the journal is replaced, or they would be lost
+    journal.lock()?;
+    if journal.metadata()?.len() == len {
+        return fs::remove_file(path);
+    }
+
+    let mut rest = Vec::new();
+    journal.seek(SeekFrom::Start(len))?;
+    journal.read_to_end(&mut rest)?;
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, rest)?;
+    fs::rename(tmp_path, path)
+}
+
+fn journal_path(db_file: &Path) -> PathBuf {
+    let mut path = OsString::from(db_file.as_os_str());
+    path.push(".journal");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{self, DatabaseOptions, ScanRoot};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_journal() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("database.anlocate");
+        assert!(Journal::load(&db_path).unwrap().is_empty());
+
+        record_add(&db_path, "a/new.txt").unwrap();
+        record_remove(&db_path, "a/old.txt").unwrap();
+        record(
+            &db_path,
+            &[
+                add(0, "b/renamed.txt"),
+                JournalEntry::Remove {
+                    root: 0,
+                    path: "a/new.txt".to_string(),
+                },
+                add(0, "a/old.txt"),
+                add(1, "a/new.txt"),
+            ],
+        )
+        .unwrap();
+
+        let journal = Journal::load(&db_path).unwrap();
+        assert_eq!(
+            added(&journal),
+            vec![(0, "a/old.txt"), (0, "b/renamed.txt"), (1, "a/new.txt")]
+        );
+        assert!(journal.overrides(0, "a/new.txt"));
+        assert!(journal.overrides(0, "a/old.txt"));
+        assert!(!journal.overrides(0, "a/other.txt"));
+        // the roots have their own changes
+        assert!(!journal.overrides(1, "a/old.txt"));
+        assert!(!journal.overrides(2, "a/new.txt"));
+
+        record(
+            &db_path,
+            &[
+                add(0, "a/b/added.txt"),
+                JournalEntry::RemoveDir {
+                    root: 0,
+                    path: "a/b".to_string(),
+                },
+            ],
+        )
+        .unwrap();
+        let journal = Journal::load(&db_path).unwrap();
+        assert!(journal.overrides(0, "a/b/c/file.txt"));
+        assert!(!journal.overrides(0, "a/bc.txt"));
+        assert!(!journal.overrides(1, "a/b/c/file.txt"));
+        assert!(!added(&journal).contains(&(0, "a/b/added.txt")));
+
+        // entries recorded after the given length are kept
+        let len = recorded_len(&db_path).unwrap();
+        record_add(&db_path, "c.txt").unwrap();
+        discard_recorded(&db_path, len).unwrap();
+        let journal = Journal::load(&db_path).unwrap();
+        assert_eq!(added(&journal), vec![(0, "c.txt")]);
+
+        discard_recorded(&db_path, recorded_len(&db_path).unwrap()).unwrap();
+        assert!(!journal_path(&db_path).exists());
+    }
+
+    #[test]
+    fn test_record_in_roots() {
+        let tmp_dir = TempDir::new().unwrap();
+        let internal = tmp_dir.path().join("internal");
+        let sdcard = tmp_dir.path().join("sdcard");
+        fs::create_dir_all(&internal).unwrap();
+        fs::create_dir_all(&sdcard).unwrap();
+        let db_path = tmp_dir.path().join("database.anlocate");
+        let options = DatabaseOptions {
+            remove_root: true,
+            ..Default::default()
+        };
+        let roots = [ScanRoot::new(&internal), ScanRoot::new(&sdcard)];
+        build::build_database_from_roots(&db_path, &roots, options).unwrap();
+
+        // the paths are stored relative to the root they are in
+        let path = |root: &Path, path: &str| root.join(path).to_str().unwrap().to_string();
+        record_add(&db_path, &path(&sdcard, "DCIM/a.jpg")).unwrap();
+        record_add(&db_path, &path(&internal, "DCIM/a.jpg")).unwrap();
+        record_remove(&db_path, &path(&sdcard, "DCIM/b.jpg")).unwrap();
+        let journal = Journal::load(&db_path).unwrap();
+        assert_eq!(added(&journal), vec![(0, "DCIM/a.jpg"), (1, "DCIM/a.jpg")]);
+        assert!(journal.overrides(1, "DCIM/b.jpg"));
+        assert!(!journal.overrides(0, "DCIM/b.jpg"));
+
+        let err = record_add(&db_path, &path(tmp_dir.path(), "other/c.jpg")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    fn add(root: usize, path: &str) -> JournalEntry {
+        JournalEntry::Add {
+            root,
+            path: path.to_string(),
+        }
+    }
+
+    fn added(journal: &Journal) -> Vec<(usize, &str)> {
+        journal
+            .added()
+            .map(|(root, path)| (root, path.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_journal_concurrent_discard() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("database.anlocate");
+        const ENTRIES: usize = 2000;
+        let writer = {
+            let db_path = db_path.clone();
+            std::thread::spawn(move || {
+                for i in 0..ENTRIES {
+                    record_add(&db_path, &format!("file{i}")).unwrap();
+                }
+            })
+        };
+
+        // no entry is lost when the journal is compacted while entries are recorded
+        let mut discarded = 0;
+        let mut count_discarded = || {
+            let len = recorded_len(&db_path).unwrap();
+            if len > 0 {
+                let journal = fs::read(journal_path(&db_path)).unwrap();
+                discarded += journal[..len as usize]
+                    .iter()
+                    .filter(|&&b| b == b'\n')
+                    .count();
+                discard_recorded(&db_path, len).unwrap();
+            }
+        };
+        while !writer.is_finished() {
+            count_discarded();
+        }
+        writer.join().unwrap();
+        count_discarded();
+        assert_eq!(discarded, ENTRIES);
+    }
+}
@@ -2,6 +2,15 @@
 pub mod android;
 pub mod build;
 pub mod compress;
+pub mod filter;
+pub mod fuzzy;
+pub mod glob;
+pub mod header;
+pub mod journal;
+mod rank;
 pub mod search;
 pub mod stat;
+mod terms;
 mod util;
+#[cfg(all(feature = "watch", any(target_os = "linux", target_os = "android")))]
+pub mod watch;
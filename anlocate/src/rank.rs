@@ -0,0 +1,112 @@
+/// The term matches the basename and not only a directory.
+const BASENAME_MATCH: i32 = 30;
+/// The term matches one of the directories.
+const DIR_MATCH: i32 = 10;
+/// The basename starts with the term.
+const PREFIX_BONUS: i32 = 20;
+/// The term starts at a word boundary in the basename (after a separator or at a camelCase
+/// transition).
+const WORD_BOUNDARY_BONUS: i32 = 10;
+/// The basename without its extension is the term.
+const EXACT_BONUS: i32 = 15;
+const DEPTH_PENALTY: i32 = 2;
+/// One point is subtracted per this many bytes of the path.
+const LENGTH_PENALTY_BYTES: usize = 10;
+
+/// Returns the relevance of `path` for the (include) substring `terms`. Higher is more relevant.
+pub(crate) fn relevance(path: &str, terms: &[&str]) -> i32 {
+    let (dir, basename) = match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    };
+    let lower_basename = lowercase(basename);
+    let lower_dir = lowercase(dir);
+
+    let mut score = 0;
+    for term in terms {
+        let term = lowercase(term);
+        if term.is_empty() {
+            continue;
+        }
+        let positions: Vec<usize> = lower_basename
+            .match_indices(term.as_str())
+            .map(|(i, _)| i)
+            .collect();
+        if positions.is_empty() {
+            if lower_dir.contains(term.as_str()) {
+                score += DIR_MATCH;
+            }
+            continue;
+        }
+        score += BASENAME_MATCH;
+        if positions[0] == 0 {
+            score += PREFIX_BONUS;
+        }
+        if positions
+            .iter()
+            .any(|&i| is_word_boundary(basename, &lower_basename, i))
+        {
+            score += WORD_BOUNDARY_BONUS;
+        }
+        let stem = match lower_basename.rfind('.') {
+            Some(i) if i > 0 => &lower_basename[..i],
+            _ => &lower_basename,
+        };
+        if stem == term {
+            score += EXACT_BONUS;
+        }
+    }
+
+    let depth = path.matches('/').count() as i32;
+    let length = (path.len() / LENGTH_PENALTY_BYTES) as i32;
+    score - depth * DEPTH_PENALTY - length
+}
+
+fn lowercase(s: &str) -> String {
+    if s.is_ascii() {
+        s.to_ascii_lowercase()
+    } else {
+        s.to_lowercase()
+    }
+}
+
+/// Returns true if the match at byte index `i` of the lowercased basename starts a word.
+fn is_word_boundary(basename: &str, lower_basename: &str, i: usize) -> bool {
+    let Some(prev) = lower_basename[..i].chars().next_back() else {
+        return true;
+    };
+    if !prev.is_alphanumeric() {
+        return true;
+    }
+    // camelCase can only be detected if lowercasing did not move the byte indexes
+    if basename.len() == lower_basename.len() && basename.is_char_boundary(i) {
+        let prev = basename[..i].chars().next_back();
+        let curr = basename[i..].chars().next();
+        if let (Some(prev), Some(curr)) = (prev, curr) {
+            return prev.is_lowercase() && curr.is_uppercase();
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relevance() {
+        let relevance = |path: &str| relevance(path, &["photo"]);
+
+        // basename match over directory match
+        assert!(relevance("a/b/my_photo.jpg") > relevance("a/photos/b.jpg"));
+        // prefix over word boundary over inside a word
+        assert!(relevance("a/photo_1.jpg") > relevance("a/my_photo.jpg"));
+        assert!(relevance("a/my_photo.jpg") > relevance("a/myphotos.jpg"));
+        assert!(relevance("a/myPhotos.jpg") > relevance("a/myphotos.jpg"));
+        // exact name
+        assert!(relevance("a/Photo.jpg") > relevance("a/Photos.jpg"));
+        // shallower and shorter paths
+        assert!(relevance("a/photo.jpg") > relevance("a/b/photo.jpg"));
+        assert!(relevance("a/photo_1.jpg") > relevance("a/photo_1_with_a_long_name.jpg"));
+    }
+}
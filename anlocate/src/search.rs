@@ -1,120 +1,1804 @@
-use crate::util;
-use std::cmp::PartialEq;
-use std::io::{self, BufReader, Read};
+use crate::filter::Filter;
+use crate::fuzzy::FuzzyPattern;
+use crate::glob::Glob;
+use crate::header::RootTableEntry;
+use crate::journal::Journal;
+use crate::terms::TermSet;
+use crate::util::CaselessNeedle;
+use crate::{compress, header, rank, util};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::cmp::{Ordering, PartialEq, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, Metadata};
+use std::io::{self, BufReader, Read, Seek};
+use std::mem;
+use std::ops::Range;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
 use std::string::FromUtf8Error;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+use std::time::Instant;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MatchType {
     Include,
     Exclude,
 }
 
+/// How a query compares upper and lower case letters.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CaseMode {
+    #[default]
+    Insensitive,
+    Sensitive,
+    /// Sensitive if the query has an uppercase letter, otherwise insensitive.
+    Smart,
+}
+
+/// Where in the scope a substring query must match, written `^term`, `term$` and `^term$` in a
+/// parsed query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Start,
+    End,
+    /// The whole scope.
+    Both,
+}
+
+/// The part of the path a query is matched against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchScope {
+    Path,
+    /// The last component of the path.
+    Basename,
+    /// The path without the basename, e.g. `DCIM/Camera` for `DCIM/Camera/a.jpg`.
+    Directory,
+    /// The component with this index in the path, e.g. 1 is `Camera` in `DCIM/Camera/a.jpg`. A
+    /// path with fewer components does not match.
+    Component(usize),
+}
+
+impl MatchScope {
+    /// Returns the part of `path` that is in scope.
+    fn apply(self, path: &str) -> Option<&str> {
+        self.range(path).map(|range| &path[range])
+    }
+
+    /// Returns the byte range of the part of `path` that is in scope.
+    fn range(self, path: &str) -> Option<Range<usize>> {
+        match self {
+            MatchScope::Path => Some(0..path.len()),
+            MatchScope::Basename => Some(path.rfind('/').map_or(0, |i| i + 1)..path.len()),
+            MatchScope::Directory => Some(0..path.rfind('/').unwrap_or(0)),
+            MatchScope::Component(index) => {
+                let mut start = 0;
+                let mut components = path.split('/').filter_map(|component| {
+                    let range = start..start + component.len();
+                    start = range.end + 1;
+                    (!component.is_empty()).then_some(range)
+                });
+                components.nth(index)
+            }
+        }
+    }
+}
+
+/// A query that is matched against each path. Queries can be combined into a tree with
+/// [`SearchQuery::all`] and [`SearchQuery::any`], or parsed from text with [`parse_query`].
 #[derive(Debug)]
 pub struct SearchQuery<'a> {
-    query: &'a str,
+    query: Cow<'a, str>,
+    /// The query that the matcher is built from, which has no accents if they are ignored.
+    pattern: Cow<'a, str>,
     match_type: MatchType,
-    ascii_only: bool,
+    /// The folded pattern of a case-insensitive contains query.
+    needle: CaselessNeedle,
+    case_sensitive: bool,
+    ignore_accents: bool,
+    scope: MatchScope,
+    anchor: Option<Anchor>,
+    matcher: Matcher,
+    /// The queries that are combined by an `All` or `Any` matcher.
+    children: Vec<SearchQuery<'a>>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Contains,
+    Glob(Glob),
+    Regex(Regex),
+    Fuzzy(FuzzyPattern),
+    Filter(Filter),
+    All,
+    Any,
 }
 
 impl<'a> SearchQuery<'a> {
+    /// A query that matches paths that contain `query` (ignoring case).
     pub fn new(query: &'a str, match_type: MatchType) -> Self {
+        SearchQuery::contains(Cow::Borrowed(query), match_type)
+    }
+
+    fn contains(query: Cow<'a, str>, match_type: MatchType) -> Self {
         SearchQuery {
+            needle: CaselessNeedle::new(&query),
+            pattern: query.clone(),
             query,
             match_type,
-            ascii_only: query.is_ascii(),
+            case_sensitive: false,
+            ignore_accents: false,
+            scope: MatchScope::Path,
+            anchor: None,
+            matcher: Matcher::Contains,
+            children: Vec::new(),
+        }
+    }
+
+    /// A query that matches if all of `queries` match. The score is the sum of their scores.
+    pub fn all(mut queries: Vec<SearchQuery<'a>>, match_type: MatchType) -> Self {
+        // the queries that read the file system are only matched if the others match
+        if queries.iter().any(SearchQuery::needs_metadata) {
+            queries.sort_by_key(SearchQuery::needs_metadata);
+        }
+        SearchQuery {
+            matcher: Matcher::All,
+            children: queries,
+            ..SearchQuery::new("", match_type)
+        }
+    }
+
+    /// A query that matches if any of `queries` matches. The score is the best of their scores.
+    pub fn any(queries: Vec<SearchQuery<'a>>, match_type: MatchType) -> Self {
+        SearchQuery {
+            matcher: Matcher::Any,
+            children: queries,
+            ..SearchQuery::new("", match_type)
+        }
+    }
+
+    /// A query that matches the entries that `filter` matches.
+    pub fn filter(filter: Filter, match_type: MatchType) -> Self {
+        SearchQuery {
+            matcher: Matcher::Filter(filter),
+            ..SearchQuery::new("", match_type)
+        }
+    }
+
+    fn is_compound(&self) -> bool {
+        matches!(self.matcher, Matcher::All | Matcher::Any)
+    }
+
+    fn map_children(mut self, f: impl Fn(SearchQuery<'a>) -> SearchQuery<'a>) -> Self {
+        self.children = mem::take(&mut self.children).into_iter().map(f).collect();
+        self
+    }
+
+    /// Matches the query against the `scope` of the path instead of the whole path.
+    pub fn with_scope(self, scope: MatchScope) -> Self {
+        SearchQuery { scope, ..self }.map_children(|query| query.with_scope(scope))
+    }
+
+    /// Matches a substring query only at the start and/or end of its scope. Other queries are not
+    /// changed.
+    pub fn with_anchor(self, anchor: Anchor) -> Self {
+        match self.matcher {
+            Matcher::Contains => SearchQuery {
+                anchor: Some(anchor),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Compares letters according to `case` instead of ignoring case. Smart case is decided for
+    /// each query of a tree on its own.
+    pub fn with_case(self, case: CaseMode) -> Self {
+        if self.is_compound() {
+            return self.map_children(|query| query.with_case(case));
+        }
+        let case_sensitive = match case {
+            CaseMode::Insensitive => false,
+            CaseMode::Sensitive => true,
+            CaseMode::Smart => {
+                has_uppercase(&self.query, matches!(self.matcher, Matcher::Regex(_)))
+            }
+        };
+        if case_sensitive == self.case_sensitive {
+            return self;
+        }
+        SearchQuery {
+            case_sensitive,
+            ..self
+        }
+        .rebuild_matcher()
+    }
+
+    /// Ignores diacritics and Unicode normalization if `ignore` is true, so that e.g. "ake"
+    /// matches "Åke" in both NFC and NFD. Regular expressions are always matched as is.
+    pub fn with_ignore_accents(self, ignore: bool) -> Self {
+        if self.is_compound() {
+            return self.map_children(|query| query.with_ignore_accents(ignore));
+        }
+        if ignore == self.ignore_accents || matches!(self.matcher, Matcher::Regex(_)) {
+            return self;
+        }
+        let pattern = match util::fold_accents(&self.query) {
+            Cow::Owned(folded) if ignore => Cow::Owned(folded),
+            _ => self.query.clone(),
+        };
+        SearchQuery {
+            pattern,
+            ignore_accents: ignore,
+            ..self
+        }
+        .rebuild_matcher()
+    }
+
+    /// Builds the matcher again after the pattern or the case sensitivity changed.
+    fn rebuild_matcher(mut self) -> Self {
+        let pattern = &self.pattern;
+        self.needle = CaselessNeedle::new(pattern);
+        self.matcher = match &self.matcher {
+            Matcher::Contains => Matcher::Contains,
+            Matcher::Glob(_) => Matcher::Glob(Glob::with_case(pattern, self.case_sensitive)),
+            Matcher::Fuzzy(_) => {
+                Matcher::Fuzzy(FuzzyPattern::with_case(pattern, self.case_sensitive))
+            }
+            Matcher::Regex(_) => Matcher::Regex(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(!self.case_sensitive)
+                    .build()
+                    .expect("the pattern compiled before"),
+            ),
+            Matcher::Filter(filter) => Matcher::Filter(filter.clone()),
+            Matcher::All => Matcher::All,
+            Matcher::Any => Matcher::Any,
+        };
+        self
+    }
+
+    /// Turns an include query into an exclude query and the other way around.
+    fn negated(self) -> Self {
+        let match_type = match self.match_type {
+            MatchType::Include => MatchType::Exclude,
+            MatchType::Exclude => MatchType::Include,
+        };
+        SearchQuery { match_type, ..self }
+    }
+
+    /// A query that matches if the glob `pattern` (ignoring case) matches the whole `scope` of the
+    /// path. See [`Glob`] for the syntax.
+    pub fn glob(pattern: &'a str, match_type: MatchType, scope: MatchScope) -> Self {
+        SearchQuery {
+            scope,
+            matcher: Matcher::Glob(Glob::new(pattern)),
+            ..SearchQuery::new(pattern, match_type)
+        }
+    }
+
+    /// A query that matches if the characters of `pattern` occur in order (ignoring case) in the
+    /// `scope` of the path. The hits are scored by how good the match is, see [`FuzzyPattern`].
+    pub fn fuzzy(pattern: &'a str, match_type: MatchType, scope: MatchScope) -> Self {
+        SearchQuery {
+            scope,
+            matcher: Matcher::Fuzzy(FuzzyPattern::new(pattern)),
+            ..SearchQuery::new(pattern, match_type)
+        }
+    }
+
+    /// A query that matches if the regular expression `pattern` matches anywhere in the `scope` of
+    /// the path. The pattern is case-insensitive unless it turns it off with `(?-i)`.
+    pub fn regex(
+        pattern: &'a str,
+        match_type: MatchType,
+        scope: MatchScope,
+    ) -> Result<Self, SearchError> {
+        let regex = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+        Ok(SearchQuery {
+            scope,
+            matcher: Matcher::Regex(regex),
+            ..SearchQuery::new(pattern, match_type)
+        })
+    }
+
+    /// Returns true if matching the query reads the metadata of the entry.
+    fn needs_metadata(&self) -> bool {
+        match &self.matcher {
+            Matcher::Filter(filter) => filter.needs_metadata(),
+            Matcher::All | Matcher::Any => self.children.iter().any(SearchQuery::needs_metadata),
+            _ => false,
+        }
+    }
+
+    /// Returns the score of the entry if it matches the query, ignoring whether it is an include
+    /// or exclude query. Only fuzzy queries (and trees of them) have a non-zero score.
+    fn match_score(&self, entry: &Entry) -> Option<i32> {
+        match &self.matcher {
+            Matcher::All => return search_match_score(entry, &self.children),
+            Matcher::Any => {
+                return self
+                    .children
+                    .iter()
+                    .filter_map(|query| query.term_score(entry))
+                    .max()
+            }
+            Matcher::Filter(filter) => {
+                let metadata = filter.needs_metadata().then(|| entry.metadata()).flatten();
+                return filter.is_match(entry.path, metadata).then_some(0);
+            }
+            _ => {}
+        }
+        let text = self.scope.apply(entry.path)?;
+        let text = if self.ignore_accents {
+            util::fold_accents(text)
+        } else {
+            Cow::Borrowed(text)
+        };
+        let text = text.as_ref();
+        let is_match = match &self.matcher {
+            Matcher::Contains => self.contains_match(text),
+            Matcher::Glob(glob) => glob.is_match(text),
+            Matcher::Regex(regex) => regex.is_match(text),
+            Matcher::Fuzzy(fuzzy) => return fuzzy.score(text),
+            Matcher::Filter(_) | Matcher::All | Matcher::Any => unreachable!(),
+        };
+        is_match.then_some(0)
+    }
+
+    /// Returns true if the (folded) `text` has the pattern of a substring query where it is
+    /// anchored.
+    fn contains_match(&self, text: &str) -> bool {
+        let pattern = self.pattern.as_ref();
+        match (self.anchor, self.case_sensitive) {
+            (None, true) => text.contains(pattern),
+            (None, false) => self.needle.is_in(text),
+            (Some(Anchor::Start), true) => text.starts_with(pattern),
+            (Some(Anchor::Start), false) => self.needle.is_prefix_of(text),
+            (Some(Anchor::End), true) => text.ends_with(pattern),
+            (Some(Anchor::End), false) => self.needle.is_suffix_of(text),
+            (Some(Anchor::Both), true) => text == pattern,
+            (Some(Anchor::Both), false) => self.needle.is_equal_to(text),
+        }
+    }
+
+    /// Adds the byte ranges of `path` that the include queries match to `ranges`. A glob matches
+    /// the whole scope, and a fuzzy query the chars of its best match.
+    fn highlights(&self, path: &str, ranges: &mut Vec<Range<usize>>) {
+        if self.match_type == MatchType::Exclude {
+            return;
+        }
+        if self.is_compound() {
+            for query in &self.children {
+                query.highlights(path, ranges);
+            }
+            return;
+        }
+        let Some(scope) = self.scope.range(path) else {
+            return;
+        };
+        let text = &path[scope.clone()];
+        let fold_accents = |c: &str| {
+            if self.ignore_accents {
+                util::fold_accents(c).into_owned()
+            } else {
+                c.to_string()
+            }
+        };
+        let start = ranges.len();
+        match &self.matcher {
+            Matcher::Contains => {
+                let (folded, origins) = util::fold_chars(text, |c| {
+                    if self.case_sensitive {
+                        fold_accents(c)
+                    } else {
+                        util::case_fold(&fold_accents(c))
+                    }
+                });
+                let needle = if self.case_sensitive {
+                    self.pattern.as_ref()
+                } else {
+                    self.needle.as_str()
+                };
+                let starts: Vec<usize> = match self.anchor {
+                    _ if needle.is_empty() => Vec::new(),
+                    None => folded.match_indices(needle).map(|(i, _)| i).collect(),
+                    Some(Anchor::Start) => folded
+                        .starts_with(needle)
+                        .then_some(0)
+                        .into_iter()
+                        .collect(),
+                    Some(Anchor::End) => folded
+                        .ends_with(needle)
+                        .then(|| folded.len() - needle.len())
+                        .into_iter()
+                        .collect(),
+                    Some(Anchor::Both) => (folded == needle).then_some(0).into_iter().collect(),
+                };
+                ranges.extend(
+                    starts
+                        .into_iter()
+                        .map(|i| origins[i].start..origins[i + needle.len() - 1].end),
+                );
+            }
+            Matcher::Regex(regex) => ranges.extend(
+                regex
+                    .find_iter(text)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.range()),
+            ),
+            Matcher::Glob(glob) => {
+                if glob.is_match(&fold_accents(text)) && !text.is_empty() {
+                    ranges.push(0..text.len());
+                }
+            }
+            Matcher::Fuzzy(fuzzy) => {
+                let (folded, origins) = util::fold_chars(text, fold_accents);
+                if let Some(positions) = fuzzy.positions(&folded) {
+                    ranges.extend(positions.into_iter().map(|i| origins[i].clone()));
+                }
+            }
+            Matcher::Filter(_) | Matcher::All | Matcher::Any => {}
+        }
+        for range in &mut ranges[start..] {
+            *range = range.start + scope.start..range.end + scope.start;
+        }
+    }
+
+    /// Returns the score of the entry if it is matched by an include query or not matched by an
+    /// exclude query.
+    fn term_score(&self, entry: &Entry) -> Option<i32> {
+        let score = self.match_score(entry);
+        match self.match_type {
+            MatchType::Include => score,
+            MatchType::Exclude if score.is_some() => None,
+            MatchType::Exclude => Some(0),
         }
     }
 }
 
+/// Returns true if `query` has an uppercase letter. In a regex the letters of escapes such as `\S`
+/// and `\p{Lu}` are not counted.
+fn has_uppercase(query: &str, is_regex: bool) -> bool {
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if is_regex && c == '\\' {
+            if let Some('p' | 'P') = chars.next() {
+                if chars.clone().next() == Some('{') {
+                    chars.find(|&c| c == '}');
+                } else {
+                    chars.next();
+                }
+            }
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Parses a query like `(jpg OR png) AND camera NOT thumb` into substring queries that must all
+/// match.
+///
+/// * Terms separated by whitespace or `AND` must all match
+/// * `OR` matches if either side matches, and binds looser than `AND`
+/// * `NOT` excludes the paths that the next term (or group) matches
+/// * `&`, `|` and `!` can be used instead of `AND`, `OR` and `NOT`
+/// * Parentheses group terms
+/// * `"quoted phrases"` are matched as is, including whitespace and operators
+/// * `\` escapes the next character
+/// * `^term` only matches at the start of the path, `term$` at the end and `^term$` the whole path
+/// * `dir:Download` matches the directories of the path and `name:IMG` the basename, see
+///   [`MatchScope`]. They can be anchored too, e.g. `name:^IMG`
+/// * `ext:pdf`, `type:file`, `size:>10M` and `modified:<7d` are filters, see [`Filter::parse`]
+///
+/// An empty query gives an empty Vec.
+pub fn parse_query(text: &str) -> Result<Vec<SearchQuery<'_>>, SearchError> {
+    let mut parser = QueryParser {
+        tokens: tokenize(text)?.into_iter().peekable(),
+        end: text.len(),
+    };
+    if parser.tokens.peek().is_none() {
+        return Ok(Vec::new());
+    }
+    let query = parser.parse_or()?;
+    if let Some((position, _)) = parser.tokens.next() {
+        // parse_or only stops early at a )
+        return Err(ParseError::UnexpectedParenthesis(position).into());
+    }
+    // a top level AND is the same as a list of queries
+    if query.match_type == MatchType::Include && matches!(query.matcher, Matcher::All) {
+        Ok(query.children)
+    } else {
+        Ok(vec![query])
+    }
+}
+
+/// The fields of `field:value` terms.
+const QUERY_FIELDS: [&str; 6] = ["dir", "name", "ext", "type", "size", "modified"];
+
+#[derive(Debug, PartialEq)]
+enum QueryToken<'a> {
+    Term(Cow<'a, str>, Option<Anchor>),
+    Field(&'a str, Cow<'a, str>, Option<Anchor>),
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
+
+/// Splits the query into tokens with their byte positions.
+fn tokenize(text: &str) -> Result<Vec<(usize, QueryToken<'_>)>, ParseError> {
+    let is_word_end = |c: char| c.is_whitespace() || "()&|!\"".contains(c);
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => QueryToken::OpenParen,
+            ')' => QueryToken::CloseParen,
+            '&' => QueryToken::And,
+            '|' => QueryToken::Or,
+            '!' => QueryToken::Not,
+            '"' => QueryToken::Term(read_quoted(text, &mut chars, start)?, None),
+            _ => {
+                let mut end = text.len();
+                let mut escaped = c == '\\';
+                while let Some(&(i, c)) = chars.peek() {
+                    if !escaped && is_word_end(c) {
+                        end = i;
+                        break;
+                    }
+                    escaped = !escaped && c == '\\';
+                    chars.next();
+                }
+                match &text[start..end] {
+                    "AND" => QueryToken::And,
+                    "OR" => QueryToken::Or,
+                    "NOT" => QueryToken::Not,
+                    word => match word.split_once(':') {
+                        Some((field, value)) if QUERY_FIELDS.contains(&field) => {
+                            // a quoted value, e.g. dir:"My Documents"
+                            let quote = chars.next_if(|&(_, c)| c == '"' && value.is_empty());
+                            match quote {
+                                Some((quote, _)) => QueryToken::Field(
+                                    field,
+                                    read_quoted(text, &mut chars, quote)?,
+                                    None,
+                                ),
+                                None => {
+                                    let (value, anchor) = split_anchors(value);
+                                    QueryToken::Field(field, unescape(value), anchor)
+                                }
+                            }
+                        }
+                        _ => {
+                            let (word, anchor) = split_anchors(word);
+                            QueryToken::Term(unescape(word), anchor)
+                        }
+                    },
+                }
+            }
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Splits an unescaped `^` at the start and `$` at the end of a word from it. A word that is only
+/// anchors is kept as is.
+fn split_anchors(word: &str) -> (&str, Option<Anchor>) {
+    let start = word.strip_prefix('^');
+    let rest = start.unwrap_or(word);
+    // an odd number of backslashes before the $ escapes it
+    let end = rest
+        .strip_suffix('$')
+        .filter(|rest| (rest.len() - rest.trim_end_matches('\\').len()) % 2 == 0);
+    let term = end.unwrap_or(rest);
+    let anchor = match (start.is_some(), end.is_some()) {
+        _ if term.is_empty() => return (word, None),
+        (true, true) => Some(Anchor::Both),
+        (true, false) => Some(Anchor::Start),
+        (false, true) => Some(Anchor::End),
+        (false, false) => None,
+    };
+    (term, anchor)
+}
+
+/// Reads a quoted phrase after its opening quote at `start`, up to the closing quote.
+fn read_quoted<'a>(
+    text: &'a str,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    start: usize,
+) -> Result<Cow<'a, str>, ParseError> {
+    let mut escaped = false;
+    for (i, c) in chars.by_ref() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Ok(unescape(&text[start + 1..i])),
+            _ => escaped = false,
+        }
+    }
+    Err(ParseError::UnclosedQuote(start))
+}
+
+/// Removes the backslashes that escape the char after them.
+fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // a backslash at the end is kept
+            '\\' => unescaped.push(chars.next().unwrap_or('\\')),
+            c => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Returns the query of a `field:value` term, or None if the value is invalid.
+fn field_query<'a>(
+    field: &str,
+    value: Cow<'a, str>,
+    anchor: Option<Anchor>,
+) -> Option<SearchQuery<'a>> {
+    let scope = match field {
+        "dir" => MatchScope::Directory,
+        "name" => MatchScope::Basename,
+        _ if anchor.is_some() => return None,
+        _ => {
+            let filter = Filter::parse(field, &value)?;
+            return Some(SearchQuery::filter(filter, MatchType::Include));
+        }
+    };
+    if value.is_empty() {
+        return None;
+    }
+    Some(contains_query(value, anchor).with_scope(scope))
+}
+
+/// A substring query of a parsed term.
+fn contains_query(term: Cow<'_, str>, anchor: Option<Anchor>) -> SearchQuery<'_> {
+    let query = SearchQuery::contains(term, MatchType::Include);
+    match anchor {
+        Some(anchor) => query.with_anchor(anchor),
+        None => query,
+    }
+}
+
+struct QueryParser<'a> {
+    tokens: std::iter::Peekable<std::vec::IntoIter<(usize, QueryToken<'a>)>>,
+    /// The length of the query, where a missing term at the end is reported.
+    end: usize,
+}
+
+impl<'a> QueryParser<'a> {
+    fn parse_or(&mut self) -> Result<SearchQuery<'a>, ParseError> {
+        let mut queries = vec![self.parse_and()?];
+        while self.tokens.next_if(|(_, t)| *t == QueryToken::Or).is_some() {
+            queries.push(self.parse_and()?);
+        }
+        Ok(match queries.len() {
+            1 => queries.remove(0),
+            _ => SearchQuery::any(queries, MatchType::Include),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<SearchQuery<'a>, ParseError> {
+        let mut queries = vec![self.parse_unary()?];
+        loop {
+            match self.tokens.peek() {
+                Some((_, QueryToken::And)) => {
+                    self.tokens.next();
+                }
+                Some((
+                    _,
+                    QueryToken::Term(..)
+                    | QueryToken::Field(..)
+                    | QueryToken::Not
+                    | QueryToken::OpenParen,
+                )) => {}
+                _ => break,
+            }
+            queries.push(self.parse_unary()?);
+        }
+        Ok(match queries.len() {
+            1 => queries.remove(0),
+            _ => SearchQuery::all(queries, MatchType::Include),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<SearchQuery<'a>, ParseError> {
+        match self.tokens.next() {
+            Some((_, QueryToken::Term(term, anchor))) => Ok(contains_query(term, anchor)),
+            Some((position, QueryToken::Field(field, value, anchor))) => {
+                field_query(field, value, anchor).ok_or(ParseError::InvalidFilter(position))
+            }
+            Some((_, QueryToken::Not)) => Ok(self.parse_unary()?.negated()),
+            Some((start, QueryToken::OpenParen)) => {
+                let query = self.parse_or()?;
+                match self.tokens.next() {
+                    Some((_, QueryToken::CloseParen)) => Ok(query),
+                    _ => Err(ParseError::UnclosedParenthesis(start)),
+                }
+            }
+            Some((position, _)) => Err(ParseError::MissingTerm(position)),
+            None => Err(ParseError::MissingTerm(self.end)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// Options for searching the database
+pub struct SearchOptions<'a> {
+    /// Changes recorded since the database was built. Removed paths are left out of the results
+    /// and added paths that match the query are merged into them.
+    pub journal: Option<&'a Journal>,
+    /// Only return hits that belong to these roots (indexes into the root table). All roots are
+    /// searched if this is None.
+    pub roots: Option<&'a [usize]>,
+    /// Prefix the hits with their root if the database was built with `remove_root`, so that they
+    /// can be opened. The query is still matched against the paths without the root.
+    pub absolute_paths: bool,
+    /// Sort the hits by relevance, most relevant first, instead of in database order. Hits where
+    /// the query matches the basename, and at its start or at a word boundary, are ranked higher,
+    /// as are shorter paths with fewer directories.
+    pub rank: bool,
+    /// Return at most this many hits. Without `rank` the database is only read until there are
+    /// more hits than this, with `rank` the whole database is read but only the most relevant
+    /// hits so far are kept in memory.
+    pub max_results: Option<usize>,
+    /// Stop searching when this is cancelled, with the status `SearchStatus::Cancelled`.
+    pub cancel: Option<&'a CancelToken>,
+    /// Stop searching when this time is reached, with the status `SearchStatus::DeadlineExceeded`.
+    pub deadline: Option<Instant>,
+    /// Set `Hit::highlights` of the returned hits.
+    pub highlight: bool,
+    /// Only return hits in this directory, e.g. `Documents/Work`, as it is in the database (without
+    /// the root if it was removed). Since the database is sorted, the entries before the
+    /// directory are skipped without being decoded and the search stops after its last entry.
+    pub subtree: Option<&'a str>,
+    /// Check if the paths of the hits still exist, as `locate -e` does. Hits that are dropped do
+    /// not count towards `max_results` or `SearchResults::total_matches`, so the search goes on
+    /// until there are enough existing hits. With `rank` every hit is checked.
+    pub missing: MissingFiles,
+}
+
+/// What to do with hits whose path was deleted after the database was built.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MissingFiles {
+    /// Return them without checking if the paths exist.
+    #[default]
+    Keep,
+    /// Leave them out of the results.
+    Drop,
+    /// Return them with `Hit::missing` set.
+    Flag,
+}
+
+/// Cancels a search (from another thread). All clones of a token share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchStatus {
+    /// The whole database was searched (or `max_results` was reached).
+    Complete,
+    /// The search was cancelled and the hits are the ones found before that.
+    Cancelled,
+    /// The deadline was reached and the hits are the ones found before that.
+    DeadlineExceeded,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResults {
+    pub hits: Vec<Hit>,
+    /// The root table of the database. This is empty if the database has no header, in which case
+    /// all hits belong to root 0.
+    pub roots: Vec<RootTableEntry>,
+    /// True if there were more hits than `SearchOptions::max_results`.
+    pub truncated: bool,
+    /// The number of paths that matched the search. Without `rank` the database is only read
+    /// until `max_results` is reached, so if the results are truncated this is a lower bound.
+    pub total_matches: usize,
+    pub status: SearchStatus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub path: String,
+    /// The index of the root the path belongs to in `SearchResults::roots`.
+    pub root: usize,
+    /// The sum of the scores of the fuzzy queries in the search (0 if there are none), plus the
+    /// relevance of the hit if the search is ranked. Higher is a better match.
+    pub score: i32,
+    /// The byte ranges of `path` that the include queries matched, sorted and without overlaps.
+    /// This is only set with `SearchOptions::highlight`.
+    pub highlights: Vec<Range<usize>>,
+    /// True if the path did not exist when the search was done. This is only set with
+    /// `MissingFiles::Flag`.
+    pub missing: bool,
+}
+
+/// The number of entries that are read between checking if the search should stop.
+const STOP_CHECK_INTERVAL: u64 = 1024;
+
 pub fn search(
     reader: &mut BufReader<impl Read>,
     search: &[SearchQuery],
 ) -> Result<Vec<String>, SearchError> {
-    let mut matches = Vec::<String>::new();
-    if search.is_empty() {
-        return Ok(matches);
+    let results = search_with_options(reader, search, &SearchOptions::default())?;
+    Ok(results.hits.into_iter().map(|hit| hit.path).collect())
+}
+
+pub fn search_with_options(
+    reader: &mut BufReader<impl Read>,
+    search: &[SearchQuery],
+    options: &SearchOptions,
+) -> Result<SearchResults, SearchError> {
+    let mut results = search_database(reader, search, options, true)?;
+    // journal hits may have been merged before the last hits from the database
+    limit_hits(&mut results, options.max_results);
+    if options.missing == MissingFiles::Flag {
+        flag_missing(&mut results);
+    }
+    if options.highlight {
+        highlight_hits(&mut results.hits, search);
+    }
+    if options.absolute_paths {
+        make_absolute(&mut results)?;
     }
+    Ok(results)
+}
 
-    let mut buf = Vec::new();
-    util::read_line(reader, &mut buf)?;
-    let first = decompress_line(&[], &buf)?;
+/// The number of paths that matched a search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchCount {
+    pub count: usize,
+    pub status: SearchStatus,
+}
 
-    // Prev is stored in this local or as the last element of `result` if it matched the search.
-    let mut prev = if is_search_match(&first, search) {
-        matches.push(first);
-        None
-    } else {
-        Some(first)
+/// Counts the paths that match the search, as `locate -c` does, without storing them. The options
+/// that only change which hits are returned and how, e.g. `max_results` and `missing`, are ignored.
+pub fn count_matches(
+    reader: &mut BufReader<impl Read>,
+    search: &[SearchQuery],
+    options: &SearchOptions,
+) -> Result<MatchCount, SearchError> {
+    let results = search_database(reader, search, options, false)?;
+    Ok(MatchCount {
+        count: results.total_matches,
+        status: results.status,
+    })
+}
+
+/// Reads the database and returns the hits of the search, or only counts them in `total_matches`
+/// if `store_hits` is false.
+fn search_database(
+    reader: &mut BufReader<impl Read>,
+    search: &[SearchQuery],
+    options: &SearchOptions,
+    store_hits: bool,
+) -> Result<SearchResults, SearchError> {
+    // a database without a header only has one root
+    let (roots, section_sizes) = match header::read_header(reader)? {
+        Some(header) => {
+            let section_sizes = header.roots.iter().map(|entry| entry.entries).collect();
+            (header.roots, section_sizes)
+        }
+        None => (Vec::new(), vec![u64::MAX]),
+    };
+    let mut results = SearchResults {
+        hits: Vec::new(),
+        roots,
+        truncated: false,
+        total_matches: 0,
+        status: SearchStatus::Complete,
+    };
+    if search.is_empty() {
+        return Ok(results);
+    }
+    let subtree = options.subtree.and_then(subtree_prefix);
+    let searches = |path: &str, root: usize| {
+        options.roots.is_none_or(|roots| roots.contains(&root))
+            && subtree
+                .as_ref()
+                .is_none_or(|prefix| path.starts_with(prefix))
+    };
+    let mut compiled = CompiledSearch::new(search);
+    let mut match_score = |path: &str, root: usize| {
+        if !searches(path, root) || options.journal.is_some_and(|j| j.overrides(root, path)) {
+            return None;
+        }
+        compiled.match_score(&Entry::new(path, removed_root(&results.roots, root)))
     };
+    let rank_terms = rank_terms(search);
+    let mut prefix_skips: Vec<PrefixSkip> = PrefixSkip::new(search)
+        .into_iter()
+        .chain(subtree.as_deref().map(PrefixSkip::subtree))
+        .collect();
+    // the rest of this section is sorted after the prefixes
+    let mut section_passed = None;
+    // ranked hits are kept here instead of in `results`
+    let mut top_hits = (store_hits && options.rank).then(|| TopHits::new(options.max_results));
+    // the hits whose paths do not exist are dropped before they count, so that they make room
+    // for more hits
+    let drop_missing = store_hits && options.missing == MissingFiles::Drop;
+    // the ranked hits that have not been checked yet, they are checked in batches
+    let mut unchecked = Vec::new();
+    // the number of hits in `results` that have been checked
+    let mut checked = 0;
 
-    loop {
+    // without ranking the first hits in the database are the ones that are returned
+    let stop_after = options.max_results.filter(|_| store_hits && !options.rank);
+    let matches = &mut results.hits;
+    let mut section = 0;
+    let mut section_remaining = section_sizes.first().copied().unwrap_or(0);
+    // Prev is stored in this local or as the last element of `result` if it matched the search.
+    let mut prev = Some(String::new());
+    let mut buf = Vec::new();
+    for entry in 0u64.. {
+        // checking the time is not free so only do it once in a while
+        if entry % STOP_CHECK_INTERVAL == 0 {
+            if options.cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+                results.status = SearchStatus::Cancelled;
+                break;
+            }
+            if options
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                results.status = SearchStatus::DeadlineExceeded;
+                break;
+            }
+        }
         buf.clear();
         if util::read_db_entry_include_newline(reader, &mut buf)? == 0 {
             // we have reached EOF
             break;
         }
-        // remove the end-of-line \n
-        buf.pop();
+        // remove the end-of-line \n
+        buf.pop();
+
+        while section_remaining == 0 && section + 1 < section_sizes.len() {
+            section += 1;
+            section_remaining = section_sizes[section];
+        }
+        section_remaining = section_remaining.saturating_sub(1);
+
+        if section_passed == Some(section) || prefix_skips.iter().any(|skip| skip.skips(&buf)) {
+            continue;
+        }
+
+        let prev_bytes = prev
+            .as_ref()
+            .unwrap_or_else(|| &matches[matches.len() - 1].path)
+            .as_bytes();
+
+        let curr = decompress_line(prev_bytes, &buf)?;
+        let mut passed = false;
+        for skip in &mut prefix_skips {
+            passed |= skip.decoded(&curr);
+        }
+        if passed {
+            if section + 1 >= section_sizes.len() {
+                break;
+            }
+            section_passed = Some(section);
+        }
+
+        let Some(score) = match_score(&curr, section) else {
+            prev = Some(curr);
+            continue;
+        };
+        if !store_hits {
+            results.total_matches += 1;
+            prev = Some(curr);
+            continue;
+        }
+        if let Some(top_hits) = &mut top_hits {
+            // the hit may be dropped from the top hits later so prev must be kept here
+            let hit = Hit {
+                path: curr.clone(),
+                root: section,
+                score: score + rank::relevance(&curr, &rank_terms),
+                highlights: Vec::new(),
+                missing: false,
+            };
+            if drop_missing {
+                unchecked.push(hit);
+                if unchecked.len() >= MISSING_CHECK_BATCH {
+                    push_existing(&results.roots, &mut unchecked, top_hits);
+                }
+            } else {
+                top_hits.push(hit);
+            }
+            prev = Some(curr);
+            continue;
+        }
+        if stop_after.is_some_and(|max| matches.len() >= max) && drop_missing {
+            retain_existing(&results.roots, matches, checked);
+            checked = matches.len();
+        }
+        if stop_after.is_some_and(|max| matches.len() >= max) {
+            results.truncated = true;
+            break;
+        }
+        matches.push(Hit {
+            path: curr,
+            root: section,
+            score,
+            highlights: Vec::new(),
+            missing: false,
+        });
+        prev = None;
+    }
+    if drop_missing {
+        retain_existing(&results.roots, &mut results.hits, checked);
+        if let Some(top_hits) = &mut top_hits {
+            push_existing(&results.roots, &mut unchecked, top_hits);
+        }
+    }
+    results.total_matches += results.hits.len();
+
+    if let Some(journal) = options.journal {
+        let mut added = journal_hits(&results.roots, search, journal, searches);
+        if drop_missing {
+            retain_existing(&results.roots, &mut added, 0);
+        }
+        match &mut top_hits {
+            Some(top_hits) => {
+                for mut hit in added {
+                    hit.score += rank::relevance(&hit.path, &rank_terms);
+                    top_hits.push(hit);
+                }
+            }
+            None => {
+                results.total_matches += added.len();
+                if store_hits {
+                    merge_hits(&mut results.hits, added);
+                }
+            }
+        }
+    }
+    if let Some(top_hits) = top_hits {
+        results.total_matches = top_hits.total;
+        results.truncated = top_hits.total > top_hits.heap.len();
+        results.hits = top_hits.into_sorted_vec();
+    }
+    Ok(results)
+}
+
+/// Skips the entries that cannot match a prefix, of an anchored query or a subtree, without
+/// decoding them.
+///
+/// When a decoded entry differs from the prefix at some byte, the entries after it that share
+/// the entry up to and including that byte (which the front coding tells) cannot match either.
+/// In the sorted database they are whole ranges, e.g. all of `Android/` for `^Download`. The
+/// first entry that shares less can still be decoded with the last decoded entry, since it only
+/// uses the shared part of it.
+struct PrefixSkip<'s> {
+    prefix: &'s [u8],
+    case_sensitive: bool,
+    /// The paths are compared byte by byte, so that the sorted order of the entries is the order
+    /// of the comparison.
+    exact: bool,
+    /// The entries that share this many bytes with the last decoded entry cannot match.
+    shared: Option<usize>,
+}
+
+impl<'s> PrefixSkip<'s> {
+    /// Returns the skip of the first anchored prefix query that all hits must match, if any.
+    fn new(search: &'s [SearchQuery]) -> Option<PrefixSkip<'s>> {
+        search.iter().find_map(|query| {
+            let is_prefix = query.match_type == MatchType::Include
+                && matches!(query.matcher, Matcher::Contains)
+                && query.scope == MatchScope::Path
+                && matches!(query.anchor, Some(Anchor::Start | Anchor::Both));
+            let prefix = if query.case_sensitive {
+                query.pattern.as_ref()
+            } else {
+                query.needle.as_str()
+            };
+            // other chars are not compared byte by byte
+            (is_prefix && !prefix.is_empty() && prefix.is_ascii()).then_some(PrefixSkip {
+                prefix: prefix.as_bytes(),
+                case_sensitive: query.case_sensitive,
+                exact: query.case_sensitive && !query.ignore_accents,
+                shared: None,
+            })
+        })
+    }
+
+    /// The skip of the paths that do not start with `prefix`.
+    fn subtree(prefix: &'s str) -> PrefixSkip<'s> {
+        PrefixSkip {
+            prefix: prefix.as_bytes(),
+            case_sensitive: true,
+            exact: true,
+            shared: None,
+        }
+    }
+
+    /// Returns true if the front-coded `line` cannot match.
+    fn skips(&self, line: &[u8]) -> bool {
+        self.shared
+            .is_some_and(|shared| compress::common_len(line) >= shared)
+    }
+
+    /// Updates the skip with a decoded entry. Returns true if the entries after it in its section
+    /// are all sorted after the prefix, so that none of them can match.
+    fn decoded(&mut self, path: &str) -> bool {
+        let path = path.as_bytes();
+        let differs = |(a, b): (&u8, &u8)| {
+            if self.case_sensitive {
+                a != b
+            } else {
+                !a.eq_ignore_ascii_case(b)
+            }
+        };
+        let mismatch = path.iter().zip(self.prefix).position(differs);
+        self.shared = mismatch
+            // a char that is not ASCII may still fold to the prefix
+            .filter(|&i| self.exact || path[i].is_ascii())
+            .map(|i| i + 1);
+        self.exact && mismatch.is_some_and(|i| path[i] > self.prefix[i])
+    }
+}
+
+/// The prefix of the paths in the directory `subtree`, or None if all paths are.
+fn subtree_prefix(subtree: &str) -> Option<String> {
+    if subtree.is_empty() {
+        return None;
+    }
+    Some(format!("{}/", subtree.trim_end_matches('/')))
+}
+
+/// The include substring terms of the search, which the relevance of a hit is based on.
+fn rank_terms<'s>(search: &'s [SearchQuery]) -> Vec<&'s str> {
+    let mut terms = Vec::new();
+    for query in search {
+        if query.match_type == MatchType::Exclude {
+            continue;
+        }
+        match query.matcher {
+            Matcher::Contains => terms.push(query.query.as_ref()),
+            Matcher::All | Matcher::Any => terms.extend(rank_terms(&query.children)),
+            _ => {}
+        }
+    }
+    terms
+}
+
+fn limit_hits(results: &mut SearchResults, max_results: Option<usize>) {
+    if let Some(max) = max_results {
+        if results.hits.len() > max {
+            results.hits.truncate(max);
+            results.truncated = true;
+        }
+    }
+}
+
+/// The number of ranked hits that are checked for missing paths at a time.
+const MISSING_CHECK_BATCH: usize = 256;
+
+/// Returns the paths of the hits on the file system. The paths must not have been made absolute.
+fn hit_paths(roots: &[RootTableEntry], hits: &[Hit]) -> Vec<PathBuf> {
+    hits.iter()
+        .map(|hit| match removed_root(roots, hit.root) {
+            Some(root) => root.join(&hit.path),
+            None => PathBuf::from(&hit.path),
+        })
+        .collect()
+}
+
+/// Removes the hits from index `from` on whose paths do not exist.
+fn retain_existing(roots: &[RootTableEntry], hits: &mut Vec<Hit>, from: usize) {
+    if from >= hits.len() {
+        return;
+    }
+    let mut exists = util::paths_exist(&hit_paths(roots, &hits[from..])).into_iter();
+    let mut i = 0;
+    hits.retain(|_| {
+        i += 1;
+        i <= from || exists.next().unwrap_or(true)
+    });
+}
+
+/// Pushes the `unchecked` hits whose paths exist to the top hits.
+fn push_existing(roots: &[RootTableEntry], unchecked: &mut Vec<Hit>, top_hits: &mut TopHits) {
+    retain_existing(roots, unchecked, 0);
+    for hit in unchecked.drain(..) {
+        top_hits.push(hit);
+    }
+}
+
+/// Drops the hits whose paths do not exist, in order, until `max_results` existing hits are found.
+/// The hits after that are not checked, and the results are truncated if there are any.
+fn take_existing(results: &mut SearchResults, max_results: Option<usize>) {
+    let max = max_results.unwrap_or(usize::MAX);
+    let mut hits = mem::take(&mut results.hits).into_iter().peekable();
+    while results.hits.len() < max && hits.peek().is_some() {
+        let from = results.hits.len();
+        results.hits.extend(hits.by_ref().take(max - from));
+        let before = results.hits.len();
+        retain_existing(&results.roots, &mut results.hits, from);
+        results.total_matches -= before - results.hits.len();
+    }
+    if hits.peek().is_some() {
+        results.truncated = true;
+    }
+}
+
+/// Sets `Hit::missing` of the hits whose paths do not exist.
+fn flag_missing(results: &mut SearchResults) {
+    let exists = util::paths_exist(&hit_paths(&results.roots, &results.hits));
+    for (hit, exists) in results.hits.iter_mut().zip(exists) {
+        hit.missing = !exists;
+    }
+}
+
+/// Sets the highlights of the hits, which must have the paths that were matched.
+fn highlight_hits(hits: &mut [Hit], search: &[SearchQuery]) {
+    for hit in hits {
+        let mut ranges = Vec::new();
+        for query in search {
+            query.highlights(&hit.path, &mut ranges);
+        }
+        ranges.sort_by_key(|range| range.start);
+        // merge overlapping and adjacent ranges
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        hit.highlights = merged;
+    }
+}
+
+/// Prefixes the hits that belong to a root that was removed from the database with that root.
+fn make_absolute(results: &mut SearchResults) -> Result<(), FromUtf8Error> {
+    for hit in &mut results.hits {
+        if let Some(root) = removed_root(&results.roots, hit.root) {
+            let path = root.join(&hit.path);
+            let path = String::from_utf8(path.into_os_string().into_vec())?;
+            // the root was added before the highlighted relative path
+            let offset = path.len() - hit.path.len();
+            for range in &mut hit.highlights {
+                *range = range.start + offset..range.end + offset;
+            }
+            hit.path = path;
+        }
+    }
+    Ok(())
+}
+
+/// Caches the hits of the last search so that a search that refines it, e.g. because the user
+/// typed one more character, only has to filter those hits instead of reading the database again.
+///
+/// A search refines the cached one if each of the cached queries is narrowed by one of its
+/// queries: an include substring that is extended, an exclude substring that is shortened, or the
+/// same query. Other queries can be added freely.
+///
+/// The cache is not invalidated when the database changes, call `clear` after building or
+/// updating it. Changes to the journal are detected.
+#[derive(Debug)]
+pub struct SearchSession {
+    /// Searches with more hits than this are not cached.
+    pub max_cached_hits: usize,
+    cached: Option<CachedSearch>,
+}
+
+#[derive(Debug)]
+struct CachedSearch {
+    queries: Vec<CachedQuery>,
+    roots: Option<Vec<usize>>,
+    subtree: Option<String>,
+    journal: Option<Journal>,
+    /// All hits of the search in database order, with relative paths.
+    results: SearchResults,
+}
+
+#[derive(Debug)]
+struct CachedQuery {
+    pattern: String,
+    match_type: MatchType,
+    case_sensitive: bool,
+    ignore_accents: bool,
+    scope: MatchScope,
+    anchor: Option<Anchor>,
+    matcher: mem::Discriminant<Matcher>,
+    filter: Option<Filter>,
+    children: Vec<CachedQuery>,
+}
+
+impl CachedQuery {
+    fn new(query: &SearchQuery) -> CachedQuery {
+        CachedQuery {
+            pattern: query.pattern.to_string(),
+            match_type: query.match_type,
+            case_sensitive: query.case_sensitive,
+            ignore_accents: query.ignore_accents,
+            scope: query.scope,
+            anchor: query.anchor,
+            matcher: mem::discriminant(&query.matcher),
+            filter: match &query.matcher {
+                Matcher::Filter(filter) => Some(filter.clone()),
+                _ => None,
+            },
+            children: query.children.iter().map(CachedQuery::new).collect(),
+        }
+    }
+}
+
+impl Default for SearchSession {
+    fn default() -> Self {
+        SearchSession {
+            max_cached_hits: 100_000,
+            cached: None,
+        }
+    }
+}
+
+impl SearchSession {
+    pub fn new() -> SearchSession {
+        SearchSession::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.cached = None;
+    }
+
+    /// Searches like [`search_with_options`], using the cached hits if possible. The reader is
+    /// rewound before it is read.
+    pub fn search(
+        &mut self,
+        reader: &mut BufReader<impl Read + Seek>,
+        search: &[SearchQuery],
+        options: &SearchOptions,
+    ) -> Result<SearchResults, SearchError> {
+        let mut results = match self.refined_hits(search, options) {
+            Some(results) => results,
+            None => {
+                self.cached = None;
+                // everything is cached so ranking, limits and absolute paths are applied after
+                let scan_options = SearchOptions {
+                    journal: options.journal,
+                    roots: options.roots,
+                    subtree: options.subtree,
+                    cancel: options.cancel,
+                    deadline: options.deadline,
+                    max_results: Some(self.max_cached_hits),
+                    ..Default::default()
+                };
+                reader.rewind()?;
+                let results = search_with_options(reader, search, &scan_options)?;
+                if results.truncated {
+                    // too many hits to cache so do a normal search
+                    reader.rewind()?;
+                    return search_with_options(reader, search, options);
+                }
+                results
+            }
+        };
+
+        if results.status == SearchStatus::Complete {
+            self.cached = Some(CachedSearch {
+                queries: search.iter().map(CachedQuery::new).collect(),
+                roots: options.roots.map(|roots| roots.to_vec()),
+                subtree: options.subtree.map(str::to_string),
+                journal: options.journal.cloned(),
+                results: results.clone(),
+            });
+        }
+
+        let drop_missing = options.missing == MissingFiles::Drop;
+        if options.rank {
+            let rank_terms = rank_terms(search);
+            // all hits are ranked when dropping missing ones, which are only checked in rank order
+            let mut top_hits = TopHits::new(options.max_results.filter(|_| !drop_missing));
+            for mut hit in mem::take(&mut results.hits) {
+                hit.score += rank::relevance(&hit.path, &rank_terms);
+                top_hits.push(hit);
+            }
+            results.truncated = top_hits.total > top_hits.heap.len();
+            results.hits = top_hits.into_sorted_vec();
+        }
+        if drop_missing {
+            take_existing(&mut results, options.max_results);
+        } else {
+            limit_hits(&mut results, options.max_results);
+        }
+        if options.missing == MissingFiles::Flag {
+            flag_missing(&mut results);
+        }
+        if options.highlight {
+            highlight_hits(&mut results.hits, search);
+        }
+        if options.absolute_paths {
+            make_absolute(&mut results)?;
+        }
+        Ok(results)
+    }
+
+    /// Returns the hits of the search by filtering the cached hits if the search refines the
+    /// cached search.
+    fn refined_hits(
+        &self,
+        search: &[SearchQuery],
+        options: &SearchOptions,
+    ) -> Option<SearchResults> {
+        let cached = self.cached.as_ref()?;
+        let same_options = cached.roots.as_deref() == options.roots
+            && cached.subtree.as_deref() == options.subtree
+            && cached.journal.as_ref() == options.journal;
+        let refines = cached
+            .queries
+            .iter()
+            .all(|cached| search.iter().any(|query| query.narrows(cached)));
+        if !same_options || !refines {
+            return None;
+        }
+
+        let mut compiled = CompiledSearch::new(search);
+        let hits: Vec<Hit> = cached
+            .results
+            .hits
+            .iter()
+            .filter_map(|hit| {
+                Some(Hit {
+                    score: compiled.match_score(&Entry::new(
+                        &hit.path,
+                        removed_root(&cached.results.roots, hit.root),
+                    ))?,
+                    ..hit.clone()
+                })
+            })
+            .collect();
+        Some(SearchResults {
+            total_matches: hits.len(),
+            hits,
+            roots: cached.results.roots.clone(),
+            truncated: false,
+            status: SearchStatus::Complete,
+        })
+    }
+}
+
+impl SearchQuery<'_> {
+    /// Returns true if the paths that this query matches are a subset of the ones `cached` matches.
+    fn narrows(&self, cached: &CachedQuery) -> bool {
+        if !self.same_kind(cached) {
+            return false;
+        }
+        if self.is_same(cached) {
+            return true;
+        }
+        if !matches!(self.matcher, Matcher::Contains) {
+            return false;
+        }
+        // an include term narrows when it is extended, and an exclude term when it is shortened,
+        // away from its anchor
+        let (longer, shorter) = match self.match_type {
+            MatchType::Include => (self.pattern.as_ref(), cached.pattern.as_str()),
+            MatchType::Exclude => (cached.pattern.as_str(), self.pattern.as_ref()),
+        };
+        if self.case_sensitive {
+            match self.anchor {
+                None => longer.contains(shorter),
+                Some(Anchor::Start) => longer.starts_with(shorter),
+                Some(Anchor::End) => longer.ends_with(shorter),
+                Some(Anchor::Both) => false,
+            }
+        } else {
+            match self.anchor {
+                None => util::caseless_contains(longer, shorter),
+                Some(Anchor::Start) => CaselessNeedle::new(shorter).is_prefix_of(longer),
+                Some(Anchor::End) => CaselessNeedle::new(shorter).is_suffix_of(longer),
+                Some(Anchor::Both) => false,
+            }
+        }
+    }
+
+    fn same_kind(&self, cached: &CachedQuery) -> bool {
+        self.match_type == cached.match_type
+            && self.case_sensitive == cached.case_sensitive
+            && self.ignore_accents == cached.ignore_accents
+            && self.scope == cached.scope
+            && self.anchor == cached.anchor
+            && mem::discriminant(&self.matcher) == cached.matcher
+    }
+
+    /// Returns true if this query matches exactly the paths that `cached` matches.
+    fn is_same(&self, cached: &CachedQuery) -> bool {
+        let same_filter = match &self.matcher {
+            Matcher::Filter(filter) => cached.filter.as_ref() == Some(filter),
+            _ => true,
+        };
+        self.same_kind(cached)
+            && self.pattern == cached.pattern
+            && same_filter
+            && self.children.len() == cached.children.len()
+            && self
+                .children
+                .iter()
+                .zip(&cached.children)
+                .all(|(query, cached)| query.is_same(cached))
+    }
+}
+
+/// The best ranked hits. If there is a max only that many hits are kept, in a min-heap so that
+/// the worst of them can be replaced by a better hit.
+struct TopHits {
+    max: Option<usize>,
+    heap: BinaryHeap<Reverse<RankedHit>>,
+    /// The number of hits that have been pushed.
+    total: usize,
+}
+
+/// Orders hits by score, and hits with the same score in database order (by root and path) with
+/// the first one being the greatest.
+struct RankedHit(Hit);
+
+impl Ord for RankedHit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .score
+            .cmp(&other.0.score)
+            .then_with(|| (other.0.root, &other.0.path).cmp(&(self.0.root, &self.0.path)))
+    }
+}
+
+impl PartialOrd for RankedHit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedHit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedHit {}
+
+impl TopHits {
+    fn new(max: Option<usize>) -> TopHits {
+        TopHits {
+            max,
+            heap: BinaryHeap::new(),
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, hit: Hit) {
+        self.total += 1;
+        let hit = Reverse(RankedHit(hit));
+        match self.max {
+            Some(max) if self.heap.len() >= max => {
+                // the top of the heap is the worst hit
+                if self.heap.peek().is_some_and(|worst| hit < *worst) {
+                    self.heap.pop();
+                    self.heap.push(hit);
+                }
+            }
+            _ => self.heap.push(hit),
+        }
+    }
+
+    /// Returns the hits, best first.
+    fn into_sorted_vec(self) -> Vec<Hit> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(RankedHit(hit))| hit)
+            .collect()
+    }
+}
+
+/// Returns the added paths in `journal` that match the search, sorted by root and path.
+fn journal_hits(
+    roots: &[RootTableEntry],
+    search: &[SearchQuery],
+    journal: &Journal,
+    searches: impl Fn(&str, usize) -> bool,
+) -> Vec<Hit> {
+    let mut compiled = CompiledSearch::new(search);
+    journal
+        .added()
+        .filter_map(|(root, path)| {
+            Some(Hit {
+                path: path.clone(),
+                root,
+                score: compiled.match_score(&Entry::new(path, removed_root(roots, root)))?,
+                highlights: Vec::new(),
+                missing: false,
+            })
+        })
+        .filter(|hit| searches(&hit.path, hit.root))
+        .collect()
+}
+
+/// Merges `added` into `hits`, both sorted by root and path.
+fn merge_hits(hits: &mut Vec<Hit>, added: Vec<Hit>) {
+    if added.is_empty() {
+        return;
+    }
+    let old_hits = mem::take(hits);
+    let mut added = added.into_iter().peekable();
+    for hit in old_hits {
+        while let Some(add) = added.next_if(|add| (add.root, &add.path) < (hit.root, &hit.path)) {
+            hits.push(add);
+        }
+        hits.push(hit);
+    }
+    hits.extend(added);
+}
 
-        let prev_bytes = prev
-            .as_ref()
-            .unwrap_or_else(|| &matches[matches.len() - 1])
-            .as_bytes();
+/// Returns the score of the path (see [`Hit::score`]) if it matches all of the queries, which are
+/// matched in order.
+fn search_match_score<'q, 'a: 'q>(
+    entry: &Entry,
+    search: impl IntoIterator<Item = &'q SearchQuery<'a>>,
+) -> Option<i32> {
+    search
+        .into_iter()
+        .map(|query| query.term_score(entry))
+        .sum()
+}
 
-        let curr = decompress_line(prev_bytes, &buf)?;
+/// A search whose top-level case-insensitive substring queries are matched against ASCII paths
+/// with a [`TermSet`], so that a path is lowercased once for all of them instead of being folded
+/// by each query.
+///
+/// The queries that read the file system are matched last, so only if the others match.
+struct CompiledSearch<'s, 'a> {
+    /// The queries of the search, with their term if they are matched with `terms`.
+    queries: Vec<(&'s SearchQuery<'a>, Option<usize>)>,
+    terms: TermSet,
+}
 
-        if is_search_match(&curr, search) {
-            matches.push(curr);
-            prev = None;
-        } else {
-            prev = Some(curr);
+impl<'s, 'a> CompiledSearch<'s, 'a> {
+    fn new(search: &'s [SearchQuery<'a>]) -> Self {
+        let mut needles: Vec<&str> = Vec::new();
+        let mut queries: Vec<_> = search
+            .iter()
+            .map(|query| {
+                let needle = query.needle.as_str();
+                // an empty needle is in all paths but the empty one, like no term is
+                let is_term = matches!(query.matcher, Matcher::Contains)
+                    && !query.case_sensitive
+                    && query.anchor.is_none()
+                    && query.scope == MatchScope::Path
+                    && !needle.is_empty()
+                    && needle.is_ascii();
+                if !is_term {
+                    return (query, None);
+                }
+                needles.push(needle);
+                (query, Some(needles.len() - 1))
+            })
+            .collect();
+        if search.iter().any(SearchQuery::needs_metadata) {
+            queries.sort_by_key(|(query, _)| query.needs_metadata());
+        }
+        CompiledSearch {
+            queries,
+            terms: TermSet::new(&needles),
         }
     }
-    Ok(matches)
-}
 
-fn is_search_match(path: &str, search: &[SearchQuery]) -> bool {
-    for query in search {
-        let hit = util::caseless_contains(path, query.query, query.ascii_only);
-        if query.match_type == MatchType::Include && !hit {
-            return false;
+    /// Returns the score of the path if it matches the search, like [`search_match_score`].
+    fn match_score(&mut self, entry: &Entry) -> Option<i32> {
+        if self.terms.is_empty() || !self.terms.set_path(entry.path) {
+            return search_match_score(entry, self.queries.iter().map(|(query, _)| *query));
         }
-        if query.match_type == MatchType::Exclude && hit {
-            return false;
+        for (query, term) in &self.queries {
+            let include = query.match_type == MatchType::Include;
+            if term.is_some_and(|term| self.terms.contains(term) != include) {
+                return None;
+            }
         }
+        // substring queries have no score
+        let rest = self
+            .queries
+            .iter()
+            .filter_map(|(query, term)| term.is_none().then_some(*query));
+        search_match_score(entry, rest)
     }
-    true
 }
 
-fn decompress_line(prev: &[u8], curr: &[u8]) -> Result<String, FromUtf8Error> {
-    #[rustfmt::skip]
-    let (data_start_index, common_len) = match curr[0] {
-        // common len is 10 (ascii value of newline)
-        251 => (1, b'\n' as usize),
-        // common len is between 251-255
-        252 => (2, curr[1] as usize),
-        // common len fits in 2-4 bytes
-        253 => (3, u32::from_le_bytes([curr[1], curr[2], 0, 0]) as usize),
-        254 => (4, u32::from_le_bytes([curr[1], curr[2], curr[3], 0]) as usize),
-        255 => (5, u32::from_le_bytes([curr[1], curr[2], curr[3], curr[4]]) as usize),
-        // common len fits in the first byte
-        len => (1, len as usize),
-    };
+/// An entry that the queries are matched against. Its metadata is only read from the file system
+/// if a query needs it.
+struct Entry<'p> {
+    path: &'p str,
+    /// The root that the path is relative to, if it was removed from the database.
+    root: Option<&'p Path>,
+    metadata: OnceCell<Option<Metadata>>,
+}
+
+impl<'p> Entry<'p> {
+    fn new(path: &'p str, root: Option<&'p Path>) -> Entry<'p> {
+        Entry {
+            path,
+            root,
+            metadata: OnceCell::new(),
+        }
+    }
 
-    let common = &prev[0..common_len];
-    let current = &curr[data_start_index..];
+    /// Returns the metadata of the entry (not following symlinks), or None if it can not be read.
+    fn metadata(&self) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| {
+                let metadata = match self.root {
+                    Some(root) => fs::symlink_metadata(root.join(self.path)),
+                    None => fs::symlink_metadata(self.path),
+                };
+                metadata.ok()
+            })
+            .as_ref()
+    }
+}
 
-    let mut result = Vec::with_capacity(common.len() + current.len());
-    result.extend_from_slice(common);
-    result.extend_from_slice(current);
+/// Returns the path of the root with this index if it was removed from the paths in the database.
+fn removed_root(roots: &[RootTableEntry], root: usize) -> Option<&Path> {
+    roots
+        .get(root)
+        .filter(|entry| entry.root_removed)
+        .map(|entry| entry.root.path.as_path())
+}
 
-    String::from_utf8(result)
+fn decompress_line(prev: &[u8], curr: &[u8]) -> Result<String, FromUtf8Error> {
+    String::from_utf8(compress::decompress_line(prev, curr))
 }
 
 #[derive(Debug)]
 pub enum SearchError {
     IO(io::Error),
     Encoding(FromUtf8Error),
+    /// The pattern of a regex query is invalid.
+    Regex(regex::Error),
+    /// The syntax of a parsed query is invalid.
+    Parse(ParseError),
+}
+
+/// An error in the syntax of a query given to [`parse_query`], with the byte position in the query
+/// where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A `(` without a `)`.
+    UnclosedParenthesis(usize),
+    /// A `)` without a `(`.
+    UnexpectedParenthesis(usize),
+    /// A `"` without a closing `"`.
+    UnclosedQuote(usize),
+    /// An operator or parenthesis where a term was expected, e.g. the end of `jpg OR`.
+    MissingTerm(usize),
+    /// A `field:value` term with an invalid value, e.g. `size:big`.
+    InvalidFilter(usize),
 }
 
 impl From<io::Error> for SearchError {
@@ -129,11 +1813,27 @@ impl From<FromUtf8Error> for SearchError {
     }
 }
 
+impl From<regex::Error> for SearchError {
+    fn from(error: regex::Error) -> Self {
+        SearchError::Regex(error)
+    }
+}
+
+impl From<ParseError> for SearchError {
+    fn from(error: ParseError) -> Self {
+        SearchError::Parse(error)
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::byte_char_slices)]
 mod tests {
     use super::*;
-    use std::fs::File;
-    use std::io::Write;
+    use crate::build::{self, DatabaseOptions, ScanRoot};
+    use crate::journal;
+    use std::fs::{self, File};
+    use std::io::{Cursor, Write};
+    use std::time::{Duration, SystemTime};
     use tempfile::TempDir;
 
     #[test]
@@ -219,6 +1919,1063 @@ mod tests {
         assert_eq!(search(&mut reader, &query(&["?"])).unwrap().len(), 1);
     }
 
+    #[test]
+    fn test_search_with_journal() {
+        let mut compressed = compress::compress_lines(&[
+            b"/usr/src/cmd/aardvark.c",
+            b"/usr/src/cmd/armadillo.c",
+            b"/usr/tmp/zoo",
+        ]);
+        compressed.push(b'\n');
+        let journal =
+            b"+0\t/usr/src/cmd/abc.c\n-0\t/usr/src/cmd/aardvark.c\n+0\t/usr/tmp/zoo\n+0\t/zzz/a\n";
+        let journal = Journal::read(&mut BufReader::new(journal.as_slice())).unwrap();
+        let options = SearchOptions {
+            journal: Some(&journal),
+            ..Default::default()
+        };
+        let search = |query: &[SearchQuery]| {
+            let mut reader = BufReader::new(compressed.as_slice());
+            let results = search_with_options(&mut reader, query, &options).unwrap();
+            results
+                .hits
+                .into_iter()
+                .map(|hit| hit.path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            search(&query(&["/a"])),
+            vec!["/usr/src/cmd/abc.c", "/usr/src/cmd/armadillo.c", "/zzz/a"]
+        );
+
+        // paths that are both in the database and added to the journal are only reported once
+        assert_eq!(search(&query(&["zoo"])), vec!["/usr/tmp/zoo"]);
+    }
+
+    #[test]
+    fn test_search_with_journal_roots() {
+        let tmp_dir = TempDir::new().unwrap();
+        let internal = tmp_dir.path().join("internal");
+        let sdcard = tmp_dir.path().join("sdcard");
+        for path in [internal.join("DCIM"), sdcard.join("DCIM")] {
+            fs::create_dir_all(&path).unwrap();
+            File::create(path.join("a.jpg")).unwrap();
+        }
+        let db_path = tmp_dir.path().join("database.anlocate");
+        let options = DatabaseOptions {
+            remove_root: true,
+            ..Default::default()
+        };
+        let roots = [ScanRoot::new(&internal), ScanRoot::new(&sdcard)];
+        build::build_database_from_roots(&db_path, &roots, options).unwrap();
+
+        // the journal paths are relative to their own root, not the first one
+        File::create(sdcard.join("DCIM/b.jpg")).unwrap();
+        fs::remove_file(sdcard.join("DCIM/a.jpg")).unwrap();
+        let path = |path: &str| sdcard.join(path).to_str().unwrap().to_string();
+        journal::record_add(&db_path, &path("DCIM/b.jpg")).unwrap();
+        journal::record_remove(&db_path, &path("DCIM/a.jpg")).unwrap();
+
+        let journal = Journal::load(&db_path).unwrap();
+        let options = SearchOptions {
+            journal: Some(&journal),
+            absolute_paths: true,
+            missing: MissingFiles::Flag,
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(File::open(&db_path).unwrap());
+        let results = search_with_options(&mut reader, &query(&["jpg"]), &options).unwrap();
+        let hits: Vec<_> = results
+            .hits
+            .into_iter()
+            .map(|hit| (PathBuf::from(hit.path), hit.root, hit.missing))
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                (internal.join("DCIM/a.jpg"), 0, false),
+                (sdcard.join("DCIM/b.jpg"), 1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_compiled_terms() {
+        let mut database = compress::compress_lines(&[
+            "Music/Abc.mp3".as_bytes(),
+            "Music/Björk/abcd.mp3".as_bytes(),
+            "Music/Straße.mp3".as_bytes(),
+            "Music/strasse/cd.mp3".as_bytes(),
+            "Music/x.mp3".as_bytes(),
+        ]);
+        database.push(b'\n');
+        let find = |text| {
+            let query = parse_query(text).unwrap();
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+
+        // ASCII paths are matched with the compiled terms, the others by each query
+        assert_eq!(find("ab BC"), ["Music/Abc.mp3", "Music/Björk/abcd.mp3"]);
+        assert_eq!(find("abc NOT cd"), ["Music/Abc.mp3"]);
+        assert_eq!(find("mp3 mp3 NOT abc"), find("mp3 NOT abc"));
+        assert_eq!(
+            find("STRASSE"),
+            ["Music/Straße.mp3", "Music/strasse/cd.mp3"]
+        );
+        assert_eq!(
+            find("music (x OR cd) NOT björk"),
+            ["Music/strasse/cd.mp3", "Music/x.mp3"]
+        );
+        assert_eq!(find("^music/s cd"), ["Music/strasse/cd.mp3"]);
+    }
+
+    #[test]
+    fn test_count_matches() {
+        let mut compressed = compress::compress_lines(&[
+            b"/usr/src/cmd/aardvark.c",
+            b"/usr/src/cmd/armadillo.c",
+            b"/usr/tmp/zoo",
+        ]);
+        compressed.push(b'\n');
+        let journal = b"+0\t/usr/src/cmd/abc.c\n-0\t/usr/src/cmd/aardvark.c\n+0\t/usr/tmp/zoo\n";
+        let journal = Journal::read(&mut BufReader::new(journal.as_slice())).unwrap();
+        let count = |text, options: &SearchOptions| {
+            let mut reader = BufReader::new(compressed.as_slice());
+            count_matches(&mut reader, &query(&[text]), options).unwrap()
+        };
+
+        let options = SearchOptions::default();
+        assert_eq!(
+            count("/a", &options),
+            MatchCount {
+                count: 2,
+                status: SearchStatus::Complete,
+            }
+        );
+        assert_eq!(count("usr", &options).count, 3);
+        assert_eq!(count("xyz", &options).count, 0);
+
+        let options = SearchOptions {
+            journal: Some(&journal),
+            // only change the hits that are returned
+            max_results: Some(1),
+            rank: true,
+            ..Default::default()
+        };
+        assert_eq!(count("/a", &options).count, 2);
+        assert_eq!(count("usr", &options).count, 3);
+    }
+
+    #[test]
+    fn test_search_roots() {
+        let header = header::DatabaseHeader {
+            roots: vec![
+                header::RootTableEntry {
+                    root: ScanRoot::new("/storage/emulated/0"),
+                    entries: 2,
+                    root_removed: false,
+                },
+                header::RootTableEntry {
+                    root: ScanRoot::new("/storage/sdcard"),
+                    entries: 0,
+                    root_removed: false,
+                },
+                header::RootTableEntry {
+                    root: ScanRoot::new("/storage/usb"),
+                    entries: 2,
+                    root_removed: false,
+                },
+            ],
+        };
+        let mut database = Vec::new();
+        header::write_header(&mut database, &header).unwrap();
+        database.extend(compress::compress_lines(&[b"DCIM/a.jpg", b"Music/a.mp3"]));
+        database.push(b'\n');
+        database.extend(compress::compress_lines(&[b"DCIM/b.jpg", b"DCIM/c.png"]));
+        database.push(b'\n');
+
+        let mut reader = BufReader::new(database.as_slice());
+        let results =
+            search_with_options(&mut reader, &query(&["jpg"]), &SearchOptions::default()).unwrap();
+        assert_eq!(
+            results.hits,
+            vec![
+                Hit {
+                    path: "DCIM/a.jpg".to_string(),
+                    root: 0,
+                    score: 0,
+                    highlights: Vec::new(),
+                    missing: false,
+                },
+                Hit {
+                    path: "DCIM/b.jpg".to_string(),
+                    root: 2,
+                    score: 0,
+                    highlights: Vec::new(),
+                    missing: false,
+                },
+            ]
+        );
+        assert_eq!(results.roots, header.roots);
+
+        let options = SearchOptions {
+            roots: Some(&[2]),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["DCIM"]), &options).unwrap();
+        assert_eq!(
+            results.hits.iter().map(|hit| hit.root).collect::<Vec<_>>(),
+            vec![2, 2]
+        );
+    }
+
+    #[test]
+    fn test_search_subtree() {
+        let header = header::DatabaseHeader {
+            roots: vec![
+                header::RootTableEntry {
+                    root: ScanRoot::new("/storage/emulated/0"),
+                    entries: 5,
+                    root_removed: false,
+                },
+                header::RootTableEntry {
+                    root: ScanRoot::new("/storage/usb"),
+                    entries: 4,
+                    root_removed: false,
+                },
+            ],
+        };
+        let mut database = Vec::new();
+        header::write_header(&mut database, &header).unwrap();
+        // entries that can be skipped without decoding them are invalid UTF-8
+        database.extend(compress::compress_lines(&[
+            b"Android/a",
+            b"Android/b\xff",
+            b"DCIM/a.jpg",
+            b"DCIMX/a.jpg",
+            b"DCIMX/b\xff",
+        ]));
+        database.push(b'\n');
+        database.extend(compress::compress_lines(&[
+            b"DCIM",
+            b"DCIM/b.jpg",
+            b"Music/a.mp3",
+            b"Music/\xff",
+        ]));
+        database.push(b'\n');
+        let journal = b"+0\tDCIM/c.jpg\n+0\tDCIMX/d.jpg\n";
+        let journal = Journal::read(&mut BufReader::new(journal.as_slice())).unwrap();
+
+        let search = |subtree: &str, roots: Option<&[usize]>| {
+            let options = SearchOptions {
+                subtree: Some(subtree),
+                roots,
+                journal: Some(&journal),
+                ..Default::default()
+            };
+            let mut reader = BufReader::new(database.as_slice());
+            let results = search_with_options(&mut reader, &query(&["jpg"]), &options).unwrap();
+            results
+                .hits
+                .into_iter()
+                .map(|hit| (hit.path, hit.root))
+                .collect::<Vec<_>>()
+        };
+
+        let expected = vec![
+            ("DCIM/a.jpg".to_string(), 0),
+            ("DCIM/c.jpg".to_string(), 0),
+            ("DCIM/b.jpg".to_string(), 1),
+        ];
+        assert_eq!(search("DCIM", None), expected);
+        assert_eq!(search("DCIM/", None), expected);
+        assert_eq!(
+            search("DCIM", Some(&[1])),
+            vec![("DCIM/b.jpg".to_string(), 1)]
+        );
+        assert_eq!(search("DCI", None), vec![]);
+    }
+
+    #[test]
+    fn test_search_absolute_paths() {
+        let header = header::DatabaseHeader {
+            roots: vec![header::RootTableEntry {
+                root: ScanRoot::new("/storage/emulated/0"),
+                entries: 2,
+                root_removed: true,
+            }],
+        };
+        let mut database = Vec::new();
+        header::write_header(&mut database, &header).unwrap();
+        database.extend(compress::compress_lines(&[b"DCIM/a.jpg", b"Music/a.mp3"]));
+        database.push(b'\n');
+
+        let mut reader = BufReader::new(database.as_slice());
+        assert_eq!(
+            search(&mut reader, &query(&["a"])).unwrap(),
+            ["DCIM/a.jpg", "Music/a.mp3"]
+        );
+
+        // the root is not part of what the query matches
+        let options = SearchOptions {
+            absolute_paths: true,
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["emulated"]), &options).unwrap();
+        assert!(results.hits.is_empty());
+
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["jpg"]), &options).unwrap();
+        assert_eq!(
+            results.hits,
+            vec![Hit {
+                path: "/storage/emulated/0/DCIM/a.jpg".to_string(),
+                root: 0,
+                score: 0,
+                highlights: Vec::new(),
+                missing: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_search_glob() {
+        let mut database = compress::compress_lines(&[
+            b"DCIM/Camera/IMG_2023.jpg",
+            b"DCIM/Camera/VID_2023.mp4",
+            b"DCIM/thumbs.jpg/list.txt",
+            b"Download/IMG_2022.JPG",
+        ]);
+        database.push(b'\n');
+        let find = |query: &[SearchQuery]| {
+            search(&mut BufReader::new(database.as_slice()), query).unwrap()
+        };
+
+        let jpg = SearchQuery::glob("*.jpg", MatchType::Include, MatchScope::Basename);
+        assert_eq!(
+            find(&[jpg]),
+            ["DCIM/Camera/IMG_2023.jpg", "Download/IMG_2022.JPG"]
+        );
+        let jpg = SearchQuery::glob("*.jpg", MatchType::Include, MatchScope::Path);
+        assert!(find(&[jpg]).is_empty());
+
+        let query = [
+            SearchQuery::glob("DCIM/**", MatchType::Include, MatchScope::Path),
+            SearchQuery::glob("*_2023.*", MatchType::Exclude, MatchScope::Basename),
+        ];
+        assert_eq!(find(&query), ["DCIM/thumbs.jpg/list.txt"]);
+    }
+
+    #[test]
+    fn test_search_regex() {
+        let mut database = compress::compress_lines(&[
+            b"DCIM/Camera/IMG_20230101.jpg",
+            b"DCIM/Camera/IMG_2023.jpg",
+            b"Download/img_20220101.JPG",
+        ]);
+        database.push(b'\n');
+        let find = |query: &[SearchQuery]| {
+            search(&mut BufReader::new(database.as_slice()), query).unwrap()
+        };
+
+        let query = [
+            SearchQuery::regex(
+                r"^img_\d{8}\.jpg$",
+                MatchType::Include,
+                MatchScope::Basename,
+            )
+            .unwrap(),
+            SearchQuery::new("download", MatchType::Exclude),
+        ];
+        assert_eq!(find(&query), ["DCIM/Camera/IMG_20230101.jpg"]);
+
+        let query = [SearchQuery::regex("(?-i)img", MatchType::Include, MatchScope::Path).unwrap()];
+        assert_eq!(find(&query), ["Download/img_20220101.JPG"]);
+
+        let invalid = SearchQuery::regex("IMG_(", MatchType::Include, MatchScope::Path);
+        assert!(matches!(invalid, Err(SearchError::Regex(_))));
+    }
+
+    #[test]
+    fn test_search_fuzzy() {
+        let mut database = compress::compress_lines(&[
+            b"Pictures/Screenshots/Screenshot_20230101.png",
+            b"Pictures/Screenshots/Screenshot_20220101.png",
+            b"scratch/nightshift2023.txt",
+        ]);
+        database.push(b'\n');
+
+        let query = [SearchQuery::fuzzy(
+            "scrnsht2023",
+            MatchType::Include,
+            MatchScope::Path,
+        )];
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query, &SearchOptions::default()).unwrap();
+        let hits: Vec<_> = results.hits.iter().map(|hit| hit.path.as_str()).collect();
+        assert_eq!(
+            hits,
+            [
+                "Pictures/Screenshots/Screenshot_20230101.png",
+                "scratch/nightshift2023.txt"
+            ]
+        );
+        assert!(results.hits[0].score > results.hits[1].score);
+    }
+
+    #[test]
+    fn test_search_rank() {
+        let mut database = compress::compress_lines(&[
+            b"Documents/photos/album.txt",
+            b"Pictures/2023/holiday/my_photo.jpg",
+            b"Pictures/myphotos.jpg",
+            b"Pictures/photo_1.jpg",
+        ]);
+        database.push(b'\n');
+
+        let options = SearchOptions {
+            rank: true,
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["photo"]), &options).unwrap();
+        let hits: Vec<_> = results.hits.iter().map(|hit| hit.path.as_str()).collect();
+        assert_eq!(
+            hits,
+            [
+                "Pictures/photo_1.jpg",
+                "Pictures/2023/holiday/my_photo.jpg",
+                "Pictures/myphotos.jpg",
+                "Documents/photos/album.txt",
+            ]
+        );
+        assert_eq!(results.total_matches, 4);
+        assert!(!results.truncated);
+
+        // only the best hits are kept
+        let options = SearchOptions {
+            rank: true,
+            max_results: Some(2),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["photo"]), &options).unwrap();
+        let hits: Vec<_> = results.hits.iter().map(|hit| hit.path.as_str()).collect();
+        assert_eq!(
+            hits,
+            ["Pictures/photo_1.jpg", "Pictures/2023/holiday/my_photo.jpg"]
+        );
+        assert_eq!(results.total_matches, 4);
+        assert!(results.truncated);
+    }
+
+    #[test]
+    fn test_search_max_results() {
+        let mut database = compress::compress_lines(&[b"a/1.txt", b"a/2.txt", b"b/3.txt"]);
+        database.push(b'\n');
+        let find = |max_results, rank| {
+            let options = SearchOptions {
+                max_results: Some(max_results),
+                rank,
+                ..Default::default()
+            };
+            let mut reader = BufReader::new(database.as_slice());
+            let results = search_with_options(&mut reader, &query(&["txt"]), &options).unwrap();
+            let hits: Vec<_> = results.hits.into_iter().map(|hit| hit.path).collect();
+            (hits, results.truncated)
+        };
+
+        assert_eq!(
+            find(2, false),
+            (vec!["a/1.txt".into(), "a/2.txt".into()], true)
+        );
+        assert!(!find(3, false).1);
+        assert_eq!(find(0, false), (vec![], true));
+        assert_eq!(find(1, true), (vec!["a/1.txt".into()], true));
+    }
+
+    #[test]
+    fn test_top_hits() {
+        // in database order
+        let hits = [
+            ("a", 0, 1),
+            ("b", 0, 5),
+            ("c", 0, 3),
+            ("d", 0, 5),
+            ("a", 1, 3),
+            ("e", 1, 2),
+        ];
+        let top = |max| {
+            let mut top_hits = TopHits::new(max);
+            for (path, root, score) in hits {
+                top_hits.push(Hit {
+                    path: path.to_string(),
+                    root,
+                    score,
+                    highlights: Vec::new(),
+                    missing: false,
+                });
+            }
+            let total = top_hits.total;
+            let hits: Vec<_> = top_hits
+                .into_sorted_vec()
+                .into_iter()
+                .map(|hit| (hit.path, hit.root))
+                .collect();
+            (hits, total)
+        };
+        let hits = |expected: &[(&str, usize)]| {
+            expected
+                .iter()
+                .map(|&(path, root)| (path.to_string(), root))
+                .collect::<Vec<_>>()
+        };
+
+        // equal scores are in database order
+        let all = hits(&[("b", 0), ("d", 0), ("c", 0), ("a", 1), ("e", 1), ("a", 0)]);
+        assert_eq!(top(None), (all.clone(), 6));
+        assert_eq!(top(Some(100)), (all, 6));
+        assert_eq!(top(Some(3)), (hits(&[("b", 0), ("d", 0), ("c", 0)]), 6));
+        assert_eq!(top(Some(1)), (hits(&[("b", 0)]), 6));
+        assert_eq!(top(Some(0)), (Vec::new(), 6));
+    }
+
+    #[test]
+    fn test_search_cancel() {
+        let mut database = compress::compress_lines(&[b"a/1.txt", b"a/2.txt", b"b/3.txt"]);
+        database.push(b'\n');
+
+        let cancel = CancelToken::new();
+        cancel.clone().cancel();
+        let options = SearchOptions {
+            cancel: Some(&cancel),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["txt"]), &options).unwrap();
+        assert_eq!(results.status, SearchStatus::Cancelled);
+        assert!(results.hits.is_empty());
+
+        let options = SearchOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["txt"]), &options).unwrap();
+        assert_eq!(results.status, SearchStatus::DeadlineExceeded);
+
+        let options = SearchOptions {
+            cancel: Some(&CancelToken::new()),
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(database.as_slice());
+        let results = search_with_options(&mut reader, &query(&["txt"]), &options).unwrap();
+        assert_eq!(results.status, SearchStatus::Complete);
+        assert_eq!(results.hits.len(), 3);
+    }
+
+    #[test]
+    fn test_search_session() {
+        let mut database = compress::compress_lines(&[
+            b"Pictures/Screenshots/Screenshot_1.png",
+            b"Pictures/Screenshots/Screenshot_2.jpg",
+            b"Pictures/screen.png",
+            b"Pictures/scream.png",
+        ]);
+        database.push(b'\n');
+        let paths = |results: SearchResults| -> Vec<String> {
+            results.hits.into_iter().map(|hit| hit.path).collect()
+        };
+
+        let mut session = SearchSession::new();
+        let options = SearchOptions::default();
+        let mut reader = BufReader::new(Cursor::new(database));
+        let results = session.search(&mut reader, &query(&["scre"]), &options);
+        assert_eq!(results.unwrap().hits.len(), 4);
+
+        // refined searches are answered from the cache, so they do not see the new database
+        let mut changed = BufReader::new(Cursor::new(Vec::new()));
+        let results = session.search(&mut changed, &query(&["screen"]), &options);
+        assert_eq!(
+            paths(results.unwrap()),
+            [
+                "Pictures/Screenshots/Screenshot_1.png",
+                "Pictures/Screenshots/Screenshot_2.jpg",
+                "Pictures/screen.png"
+            ]
+        );
+        let search = [
+            SearchQuery::new("screens", MatchType::Include),
+            SearchQuery::new("jpg", MatchType::Exclude),
+        ];
+        let options = SearchOptions {
+            rank: true,
+            ..Default::default()
+        };
+        let results = session.search(&mut changed, &search, &options);
+        assert_eq!(
+            paths(results.unwrap()),
+            ["Pictures/Screenshots/Screenshot_1.png"]
+        );
+
+        // removing the exclude term is not a refinement
+        let results = session.search(&mut reader, &query(&["screens"]), &options);
+        assert_eq!(results.unwrap().hits.len(), 2);
+    }
+
+    #[test]
+    fn test_search_scope() {
+        let mut database = compress::compress_lines(&[
+            b"/sdcard/Music/Band/song.mp3",
+            b"/sdcard/Music/music.txt",
+            b"/sdcard/Podcasts/music_news.mp3",
+        ]);
+        database.push(b'\n');
+        let find = |scope| {
+            let query = [SearchQuery::new("music", MatchType::Include).with_scope(scope)];
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+
+        assert_eq!(find(MatchScope::Path).len(), 3);
+        assert_eq!(
+            find(MatchScope::Basename),
+            ["/sdcard/Music/music.txt", "/sdcard/Podcasts/music_news.mp3"]
+        );
+        assert_eq!(
+            find(MatchScope::Directory),
+            ["/sdcard/Music/Band/song.mp3", "/sdcard/Music/music.txt"]
+        );
+        assert_eq!(
+            find(MatchScope::Component(1)),
+            ["/sdcard/Music/Band/song.mp3", "/sdcard/Music/music.txt"]
+        );
+        assert_eq!(
+            find(MatchScope::Component(2)),
+            ["/sdcard/Music/music.txt", "/sdcard/Podcasts/music_news.mp3"]
+        );
+        assert!(find(MatchScope::Component(4)).is_empty());
+
+        // a path without the component is not excluded
+        let query = [
+            SearchQuery::new("mp3", MatchType::Include),
+            SearchQuery::new("song", MatchType::Exclude).with_scope(MatchScope::Component(3)),
+        ];
+        let hits = search(&mut BufReader::new(database.as_slice()), &query).unwrap();
+        assert_eq!(hits, ["/sdcard/Podcasts/music_news.mp3"]);
+    }
+
+    #[test]
+    fn test_search_case() {
+        let mut database = compress::compress_lines(&[
+            b"/sdcard/DCIM/IMG_1.jpg",
+            b"/sdcard/Download/dcim.zip",
+            b"/sdcard/Download/img_2.jpg",
+        ]);
+        database.push(b'\n');
+        let find = |query: SearchQuery, case| {
+            let query = [query.with_case(case)];
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+        let contains = |q| SearchQuery::new(q, MatchType::Include);
+        let glob = |q| SearchQuery::glob(q, MatchType::Include, MatchScope::Basename);
+        let regex = |q| SearchQuery::regex(q, MatchType::Include, MatchScope::Path).unwrap();
+        let fuzzy = |q| SearchQuery::fuzzy(q, MatchType::Include, MatchScope::Path);
+
+        assert_eq!(find(contains("dcim"), CaseMode::Insensitive).len(), 2);
+        assert_eq!(
+            find(contains("dcim"), CaseMode::Sensitive),
+            ["/sdcard/Download/dcim.zip"]
+        );
+        assert_eq!(find(contains("dcim"), CaseMode::Smart).len(), 2);
+        assert_eq!(
+            find(contains("DCIM"), CaseMode::Smart),
+            ["/sdcard/DCIM/IMG_1.jpg"]
+        );
+        assert_eq!(
+            find(glob("IMG_*"), CaseMode::Smart),
+            ["/sdcard/DCIM/IMG_1.jpg"]
+        );
+        assert_eq!(
+            find(fuzzy("Ij"), CaseMode::Smart),
+            ["/sdcard/DCIM/IMG_1.jpg"]
+        );
+        // escapes are not uppercase letters
+        assert_eq!(find(regex(r"img_\S"), CaseMode::Smart).len(), 2);
+        assert_eq!(
+            find(regex(r"IMG_\S"), CaseMode::Smart),
+            ["/sdcard/DCIM/IMG_1.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_search_ignore_accents() {
+        let mut database = compress::compress_lines(&[
+            "/sdcard/Music/Åke.mp3".as_bytes(),
+            "/sdcard/Music/A\u{30A}sa.mp3".as_bytes(),
+            "/sdcard/Music/Ake.mp3".as_bytes(),
+            "/sdcard/Music/Café.mp3".as_bytes(),
+        ]);
+        database.push(b'\n');
+        let find = |query: SearchQuery, ignore| {
+            let query = [query.with_ignore_accents(ignore)];
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+        let contains = |q| SearchQuery::new(q, MatchType::Include);
+
+        assert_eq!(find(contains("ake"), false), ["/sdcard/Music/Ake.mp3"]);
+        assert_eq!(
+            find(contains("ake"), true),
+            ["/sdcard/Music/Åke.mp3", "/sdcard/Music/Ake.mp3"]
+        );
+        // NFC query and NFD path
+        assert_eq!(
+            find(contains("Åsa"), true),
+            ["/sdcard/Music/A\u{30A}sa.mp3"]
+        );
+        assert!(find(contains("Åsa"), false).is_empty());
+        assert_eq!(
+            find(contains("åke").with_case(CaseMode::Sensitive), true),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            find(
+                SearchQuery::glob("cafe.*", MatchType::Include, MatchScope::Basename),
+                true
+            ),
+            ["/sdcard/Music/Café.mp3"]
+        );
+        assert_eq!(
+            find(
+                SearchQuery::fuzzy("cfe", MatchType::Include, MatchScope::Path),
+                true
+            ),
+            ["/sdcard/Music/Café.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let mut database = compress::compress_lines(&[
+            b"/sdcard/DCIM/Camera/a.jpg",
+            b"/sdcard/DCIM/Camera/b.png",
+            b"/sdcard/DCIM/Camera/.thumb/a.jpg",
+            b"/sdcard/Download/camera manual.pdf",
+            b"/sdcard/Download/AND & OR.txt",
+        ]);
+        database.push(b'\n');
+        let find = |text| {
+            let query = parse_query(text).unwrap();
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+
+        assert_eq!(
+            find("(jpg OR png) AND camera NOT thumb"),
+            ["/sdcard/DCIM/Camera/a.jpg", "/sdcard/DCIM/Camera/b.png"]
+        );
+        assert_eq!(
+            find("(jpg | png) camera !thumb"),
+            find("(jpg OR png) AND camera NOT thumb")
+        );
+        // AND binds tighter than OR
+        assert_eq!(
+            find("pdf OR png camera"),
+            [
+                "/sdcard/DCIM/Camera/b.png",
+                "/sdcard/Download/camera manual.pdf"
+            ]
+        );
+        assert_eq!(find("NOT (DCIM OR pdf)"), ["/sdcard/Download/AND & OR.txt"]);
+        assert_eq!(find("camera!dcim"), ["/sdcard/Download/camera manual.pdf"]);
+        assert_eq!(find("NOT NOT manual"), find("manual"));
+        assert_eq!(
+            find("\"camera manual\""),
+            ["/sdcard/Download/camera manual.pdf"]
+        );
+        assert_eq!(find("camera\\ manual"), find("\"camera manual\""));
+        assert_eq!(find("\"AND & OR\""), ["/sdcard/Download/AND & OR.txt"]);
+        assert_eq!(find("\\AND \\& \\OR"), ["/sdcard/Download/AND & OR.txt"]);
+        assert!(parse_query("  ").unwrap().is_empty());
+
+        let error = |text| match parse_query(text) {
+            Err(SearchError::Parse(error)) => error,
+            result => panic!("{text}: {result:?}"),
+        };
+        assert_eq!(error("(jpg OR png"), ParseError::UnclosedParenthesis(0));
+        assert_eq!(error("jpg) OR png"), ParseError::UnexpectedParenthesis(3));
+        assert_eq!(error("jpg OR"), ParseError::MissingTerm(6));
+        assert_eq!(error("jpg AND OR png"), ParseError::MissingTerm(8));
+        assert_eq!(error("()"), ParseError::MissingTerm(1));
+        assert_eq!(error("a \"b"), ParseError::UnclosedQuote(2));
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("DCIM/Camera")).unwrap();
+        fs::create_dir_all(dir.path().join("Download")).unwrap();
+        fs::write(dir.path().join("DCIM/Camera/big.jpg"), vec![0; 4096]).unwrap();
+        fs::write(dir.path().join("DCIM/small.JPG"), vec![0; 10]).unwrap();
+        let old = File::create(dir.path().join("Download/camera.pdf")).unwrap();
+        old.set_modified(SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60))
+            .unwrap();
+
+        let header = header::DatabaseHeader {
+            roots: vec![header::RootTableEntry {
+                root: ScanRoot::new(dir.path().to_str().unwrap()),
+                entries: 3,
+                root_removed: true,
+            }],
+        };
+        let mut database = Vec::new();
+        header::write_header(&mut database, &header).unwrap();
+        database.extend(compress::compress_lines(&[
+            b"DCIM/Camera/big.jpg",
+            b"DCIM/small.JPG",
+            b"Download/camera.pdf",
+        ]));
+        database.push(b'\n');
+        let find = |text| {
+            let query = parse_query(text).unwrap();
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+
+        assert_eq!(find("ext:jpg"), ["DCIM/Camera/big.jpg", "DCIM/small.JPG"]);
+        assert_eq!(find("dir:camera"), ["DCIM/Camera/big.jpg"]);
+        assert_eq!(find("dir:\"camera\" NOT ext:pdf"), find("dir:camera"));
+        assert_eq!(find("dcim type:f size:<1K"), ["DCIM/small.JPG"]);
+        assert_eq!(find("type:f size:4K"), ["DCIM/Camera/big.jpg"]);
+        assert_eq!(find("camera modified:>1w"), ["Download/camera.pdf"]);
+        assert_eq!(find("ext:pdf OR (type:file size:>=4k)").len(), 2);
+        // not a field
+        assert_eq!(find("camera\\:pdf"), Vec::<String>::new());
+
+        let error = |text| match parse_query(text) {
+            Err(SearchError::Parse(error)) => error,
+            result => panic!("{text}: {result:?}"),
+        };
+        assert_eq!(error("jpg size:>abc"), ParseError::InvalidFilter(4));
+        assert_eq!(error("dir:"), ParseError::InvalidFilter(0));
+        assert_eq!(error("type:dir"), ParseError::InvalidFilter(0));
+    }
+
+    #[test]
+    fn test_search_missing() {
+        let dir = TempDir::new().unwrap();
+        for name in ["a.jpg", "c.jpg", "d.jpg"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let header = header::DatabaseHeader {
+            roots: vec![header::RootTableEntry {
+                root: ScanRoot::new(dir.path().to_str().unwrap()),
+                entries: 4,
+                root_removed: true,
+            }],
+        };
+        let mut database = Vec::new();
+        header::write_header(&mut database, &header).unwrap();
+        database.extend(compress::compress_lines(&[
+            b"a.jpg", b"b.jpg", b"c.jpg", b"d.jpg",
+        ]));
+        database.push(b'\n');
+        let search_with = |missing, max_results, rank, session: Option<&mut SearchSession>| {
+            let options = SearchOptions {
+                missing,
+                max_results,
+                rank,
+                ..Default::default()
+            };
+            let mut reader = BufReader::new(Cursor::new(database.as_slice()));
+            match session {
+                Some(session) => session.search(&mut reader, &query(&["jpg"]), &options),
+                None => search_with_options(&mut reader, &query(&["jpg"]), &options),
+            }
+            .unwrap()
+        };
+        let search = |missing, max_results| search_with(missing, max_results, false, None);
+        let paths = |results: &SearchResults| {
+            let paths: Vec<_> = results.hits.iter().map(|hit| hit.path.clone()).collect();
+            (paths, results.total_matches, results.truncated)
+        };
+
+        let all = (
+            ["a.jpg", "c.jpg", "d.jpg"].map(String::from).to_vec(),
+            3,
+            false,
+        );
+        assert_eq!(paths(&search(MissingFiles::Drop, None)), all);
+        // the dropped hits do not count towards the limit
+        assert_eq!(paths(&search(MissingFiles::Drop, Some(3))), all);
+        let first = (["a.jpg", "c.jpg"].map(String::from).to_vec(), 2, true);
+        assert_eq!(paths(&search(MissingFiles::Drop, Some(2))), first);
+        let mut session = SearchSession::new();
+        for rank in [false, true] {
+            let results = search_with(MissingFiles::Drop, Some(3), rank, None);
+            assert_eq!(results.hits.len(), 3);
+            assert_eq!(results.total_matches, 3);
+            assert!(!results.truncated);
+            let results = search_with(MissingFiles::Drop, Some(2), rank, Some(&mut session));
+            assert_eq!(results.hits.len(), 2);
+            assert!(results.truncated);
+            assert!(results.hits.iter().all(|hit| hit.path != "b.jpg"));
+        }
+
+        let results = search(MissingFiles::Flag, None);
+        let missing: Vec<_> = results.hits.iter().map(|hit| hit.missing).collect();
+        assert_eq!(missing, [false, true, false, false]);
+        assert!(search(MissingFiles::Keep, None)
+            .hits
+            .iter()
+            .all(|hit| !hit.missing));
+    }
+
+    #[test]
+    fn test_search_highlights() {
+        let header = header::DatabaseHeader {
+            roots: vec![header::RootTableEntry {
+                root: ScanRoot::new("/sdcard"),
+                entries: 3,
+                root_removed: true,
+            }],
+        };
+        let mut database = Vec::new();
+        header::write_header(&mut database, &header).unwrap();
+        database.extend(compress::compress_lines(&[
+            "DCIM/Camera/camera.jpg".as_bytes(),
+            "Music/Åke/STRASSE.mp3".as_bytes(),
+            "Music/A\u{30A}sa.mp3".as_bytes(),
+        ]));
+        database.push(b'\n');
+        let highlights = |search: &[SearchQuery], absolute_paths| {
+            let options = SearchOptions {
+                highlight: true,
+                absolute_paths,
+                ..Default::default()
+            };
+            let mut reader = BufReader::new(database.as_slice());
+            let results = search_with_options(&mut reader, search, &options).unwrap();
+            results
+                .hits
+                .into_iter()
+                .map(|hit| {
+                    let highlighted: Vec<_> = hit
+                        .highlights
+                        .iter()
+                        .map(|r| &hit.path[r.clone()])
+                        .collect();
+                    highlighted.join("|")
+                })
+                .collect::<Vec<_>>()
+        };
+        let parsed = |text| {
+            parse_query(text)
+                .unwrap()
+                .into_iter()
+                .map(|query| query.with_ignore_accents(true))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(highlights(&parsed("camera"), false), ["Camera|camera"]);
+        assert_eq!(highlights(&parsed("camera"), true), ["Camera|camera"]);
+        // overlapping matches are merged and excluded terms are not highlighted
+        assert_eq!(
+            highlights(&parsed("came amera jpg NOT mp3"), true),
+            ["Camera|camera|jpg"]
+        );
+        assert_eq!(
+            highlights(&parsed("dir:camera OR ake"), false),
+            ["Camera", "Åke"]
+        );
+        assert_eq!(highlights(&parsed("ße"), false), ["SSE"]);
+        assert_eq!(highlights(&parsed("asa"), false), ["A\u{30A}sa"]);
+
+        let fuzzy = [SearchQuery::fuzzy(
+            "dcj",
+            MatchType::Include,
+            MatchScope::Path,
+        )];
+        assert_eq!(highlights(&fuzzy, false), ["D|c|j"]);
+        let glob = [SearchQuery::glob(
+            "*.mp3",
+            MatchType::Include,
+            MatchScope::Basename,
+        )];
+        assert_eq!(highlights(&glob, false), ["STRASSE.mp3", "A\u{30A}sa.mp3"]);
+        let regex = [SearchQuery::regex("am|ss", MatchType::Include, MatchScope::Path).unwrap()];
+        assert_eq!(highlights(&regex, false), ["am|am", "SS"]);
+    }
+
+    #[test]
+    fn test_search_anchors() {
+        let paths = [
+            "Android/data/a.apk",
+            "Android/data/b.txt",
+            "Download/app.apk",
+            "Download/sub/x.APK",
+            "Sounds/a.mp3",
+            "download2/y.txt",
+            "sounds/c.mp3",
+            "ſounds/b.mp3",
+        ];
+        let lines: Vec<&[u8]> = paths.iter().map(|path| path.as_bytes()).collect();
+        let mut database = compress::compress_lines(&lines);
+        database.push(b'\n');
+        let find = |text, case| {
+            let query: Vec<_> = parse_query(text)
+                .unwrap()
+                .into_iter()
+                .map(|query| query.with_case(case))
+                .collect();
+            search(&mut BufReader::new(database.as_slice()), &query).unwrap()
+        };
+        let insensitive = |text| find(text, CaseMode::Insensitive);
+
+        assert_eq!(
+            insensitive("^download"),
+            ["Download/app.apk", "Download/sub/x.APK", "download2/y.txt"]
+        );
+        assert_eq!(
+            find("^Download/", CaseMode::Sensitive),
+            ["Download/app.apk", "Download/sub/x.APK"]
+        );
+        assert_eq!(
+            insensitive(".apk$"),
+            [
+                "Android/data/a.apk",
+                "Download/app.apk",
+                "Download/sub/x.APK"
+            ]
+        );
+        assert_eq!(insensitive("name:^x"), ["Download/sub/x.APK"]);
+        assert_eq!(insensitive("dir:data$ txt$"), ["Android/data/b.txt"]);
+        assert_eq!(
+            insensitive("^sounds/"),
+            ["Sounds/a.mp3", "sounds/c.mp3", "ſounds/b.mp3"]
+        );
+        assert_eq!(insensitive("^download2/y.txt$"), ["download2/y.txt"]);
+        assert!(insensitive("^download$").is_empty());
+        assert_eq!(
+            insensitive("^a NOT ^android/data/b"),
+            ["Android/data/a.apk"]
+        );
+        // escaped and lone anchors are matched literally
+        assert!(insensitive("\\^down").is_empty());
+        assert!(insensitive("apk\\$").is_empty());
+        assert!(insensitive("^").is_empty());
+
+        // the entries that share `A` with the mismatching `Android/data/a.apk` are skipped
+        let query = parse_query("^download").unwrap();
+        let mut skip = PrefixSkip::new(&query).unwrap();
+        skip.decoded("Android/data/a.apk");
+        assert!(skip.skips(&compress::compress_lines(&[b"Android/data/a.apk", b"Apps"])[20..]));
+        assert!(!skip.skips(&compress::compress_lines(&[b"Android/data/a.apk", b"Download"])[20..]));
+        skip.decoded("ſounds/b.mp3");
+        assert_eq!(skip.shared, None);
+    }
+
     fn query<'a>(q: &'a [&str]) -> Vec<SearchQuery<'a>> {
         q.iter()
             .map(|s| SearchQuery::new(s, MatchType::Include))
@@ -1,4 +1,4 @@
-use crate::util;
+use crate::{header, util};
 use std::io::{self, BufReader, Read};
 
 #[derive(Debug, PartialEq)]
@@ -8,6 +8,7 @@ pub struct Stats {
 }
 
 pub fn get_stats(database_file_reader: &mut BufReader<impl Read>) -> Result<Stats, StatsError> {
+    header::read_header(database_file_reader)?;
     let mut buf = vec![];
     let mut lines = 0;
     let mut bytes = 0;
@@ -40,6 +41,7 @@ impl From<io::Error> for StatsError {
 }
 
 #[cfg(test)]
+#[allow(clippy::byte_char_slices)]
 mod tests {
     use super::*;
     use std::fs::File;
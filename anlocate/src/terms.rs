@@ -0,0 +1,102 @@
+use aho_corasick::{AhoCorasick, AhoCorasickKind, MatchKind};
+
+/// Searches a path for substrings ignoring ASCII case. The path is lowercased once and all terms
+/// are found in it in one pass of a single Aho-Corasick automaton, whose prefilter skips to the
+/// possible starts of a term with memchr or SIMD (Teddy) searches. Only ASCII paths are searched,
+/// since case folding other chars can change their length, other paths are left to the Unicode
+/// matching of the queries.
+#[derive(Debug)]
+pub(crate) struct TermSet {
+    /// None if there are no terms.
+    automaton: Option<AhoCorasick>,
+    /// The lowercased path, kept to reuse its allocation.
+    text: Vec<u8>,
+    /// Whether the path has each term.
+    found: Vec<bool>,
+}
+
+impl TermSet {
+    /// The terms must be lowercase ASCII.
+    pub(crate) fn new(terms: &[&str]) -> TermSet {
+        debug_assert!(terms
+            .iter()
+            .all(|term| term.is_ascii() && !term.bytes().any(|b| b.is_ascii_uppercase())));
+        // the automaton cannot be built if the terms are too large, and then the queries match
+        // the paths themselves
+        let automaton = (!terms.is_empty())
+            .then(|| {
+                AhoCorasick::builder()
+                    .match_kind(MatchKind::Standard)
+                    .kind(Some(AhoCorasickKind::DFA))
+                    .prefilter(true)
+                    .build(terms)
+                    .ok()
+            })
+            .flatten();
+        TermSet {
+            automaton,
+            text: Vec::new(),
+            found: vec![false; terms.len()],
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.automaton.is_none()
+    }
+
+    /// Searches the path for the terms. Returns false if it is not ASCII, and then it cannot be
+    /// searched.
+    pub(crate) fn set_path(&mut self, path: &str) -> bool {
+        let Some(automaton) = &self.automaton else {
+            return false;
+        };
+        if !path.is_ascii() {
+            return false;
+        }
+        self.text.clear();
+        self.text
+            .extend(path.bytes().map(|byte| byte.to_ascii_lowercase()));
+
+        self.found.fill(false);
+        let mut remaining = self.found.len();
+        // overlapping, since a term can start inside another, e.g. "bc" in "abc"
+        for found in automaton.find_overlapping_iter(&self.text) {
+            let found = &mut self.found[found.pattern().as_usize()];
+            if !*found {
+                *found = true;
+                remaining -= 1;
+                if remaining == 0 {
+                    break;
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns true if the path has the term with this index.
+    pub(crate) fn contains(&self, term: usize) -> bool {
+        self.found[term]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_term_set() {
+        assert!(TermSet::new(&[]).is_empty());
+
+        let mut terms = TermSet::new(&["jpg", "ab", "bc"]);
+        assert!(terms.set_path("DCIM/ABC.JPG"));
+        assert!(terms.contains(0) && terms.contains(1) && terms.contains(2));
+        assert!(terms.set_path("DCIM/ab.png"));
+        assert!(!terms.contains(0) && terms.contains(1) && !terms.contains(2));
+        assert!(!terms.set_path("Björk.jpg"));
+
+        // the same term twice and a term inside another
+        let mut terms = TermSet::new(&["camera", "camera", "mer"]);
+        assert!(terms.set_path("DCIM/Camera/1.jpg"));
+        assert!(terms.contains(0) && terms.contains(1) && terms.contains(2));
+    }
+}
@@ -1,5 +1,12 @@
+use std::borrow::Cow;
+use std::fs;
 use std::io::{self, BufRead, BufReader, Read};
-use unicase::UniCase;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::Path;
+use std::thread;
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::UnicodeNormalization;
 
 pub fn read_line(reader: &mut BufReader<impl Read>, buf: &mut Vec<u8>) -> io::Result<usize> {
     let bytes_read = reader.read_until(b'\n', buf)?;
@@ -55,36 +62,162 @@ fn last_char_is_line_sep_newline(buf: &[u8]) -> bool {
 }
 
 /// Returns true if a contains b using Unicode-aware case-insensitive compare
-/// note: does not do normalization
-pub fn caseless_contains(a: &str, b: &str, b_is_ascii: bool) -> bool {
-    let a_is_ascii = a.is_ascii();
-    let b = if b_is_ascii {
-        UniCase::ascii(b)
-    } else {
-        UniCase::unicode(b)
-    };
+/// note: does not do normalization, see [`fold_accents`]
+pub fn caseless_contains(a: &str, b: &str) -> bool {
+    CaselessNeedle::new(b).is_in(a)
+}
+
+/// A string to search for ignoring case. Both the needle and the haystack are compared using full
+/// Unicode case folding, so folds that change the length of the text also match, e.g. "STRASSE"
+/// is found in "Straße".
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaselessNeedle {
+    folded: String,
+}
+
+impl CaselessNeedle {
+    pub fn new(needle: &str) -> CaselessNeedle {
+        CaselessNeedle {
+            folded: case_fold(needle),
+        }
+    }
+
+    /// The case folded needle.
+    pub fn as_str(&self) -> &str {
+        &self.folded
+    }
 
-    for (i, _) in a.char_indices() {
-        let haystack = &a[i..];
-        if haystack.len() < b.len() {
-            break;
+    /// Returns true if `haystack` contains the needle. An empty needle is in any haystack except
+    /// an empty one.
+    pub fn is_in(&self, haystack: &str) -> bool {
+        if haystack.is_empty() {
+            return false;
         }
-        let haystack = if haystack.is_char_boundary(b.len()) {
-            &haystack[..b.len()]
-        } else {
-            continue;
-        };
-
-        let haystack_case = if a_is_ascii {
-            UniCase::ascii(haystack)
-        } else {
-            UniCase::new(haystack)
-        };
-        if haystack_case == b {
+        if self.folded.is_empty() {
             return true;
         }
+        if haystack.is_ascii() {
+            // ASCII folds to ASCII, so a needle with other chars cannot match
+            return self.folded.is_ascii()
+                && haystack
+                    .as_bytes()
+                    .windows(self.folded.len())
+                    .any(|window| window.eq_ignore_ascii_case(self.folded.as_bytes()));
+        }
+        caseless::default_case_fold_str(haystack).contains(&self.folded)
+    }
+
+    /// Returns true if `haystack` starts with the needle.
+    pub fn is_prefix_of(&self, haystack: &str) -> bool {
+        if haystack.is_ascii() {
+            let len = self.folded.len();
+            return haystack.len() >= len
+                && haystack.as_bytes()[..len].eq_ignore_ascii_case(self.folded.as_bytes());
+        }
+        case_fold(haystack).starts_with(&self.folded)
+    }
+
+    /// Returns true if `haystack` ends with the needle.
+    pub fn is_suffix_of(&self, haystack: &str) -> bool {
+        if haystack.is_ascii() {
+            let len = self.folded.len();
+            return haystack.len() >= len
+                && haystack.as_bytes()[haystack.len() - len..]
+                    .eq_ignore_ascii_case(self.folded.as_bytes());
+        }
+        case_fold(haystack).ends_with(&self.folded)
+    }
+
+    /// Returns true if `haystack` is the needle.
+    pub fn is_equal_to(&self, haystack: &str) -> bool {
+        if haystack.is_ascii() {
+            return haystack.eq_ignore_ascii_case(&self.folded);
+        }
+        case_fold(haystack) == self.folded
+    }
+}
+
+/// Returns `s` with full Unicode case folding.
+pub fn case_fold(s: &str) -> String {
+    if s.is_ascii() {
+        s.to_ascii_lowercase()
+    } else {
+        caseless::default_case_fold_str(s)
     }
-    false
+}
+
+/// Folds `text` one char at a time with `fold`. Returns the folded text and, for each of its
+/// bytes, the byte range in `text` of the char it was folded from. A char that folds to nothing
+/// is added to the range of the byte before it.
+pub fn fold_chars(text: &str, fold: impl Fn(&str) -> String) -> (String, Vec<Range<usize>>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut origins: Vec<Range<usize>> = Vec::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        let origin = i..i + c.len_utf8();
+        let c = fold(&text[origin.clone()]);
+        if c.is_empty() {
+            if let Some(last) = origins.last_mut() {
+                last.end = origin.end;
+            }
+        }
+        folded.push_str(&c);
+        origins.resize(folded.len(), origin);
+    }
+    (folded, origins)
+}
+
+/// Returns `s` in NFC without diacritics, e.g. "Åke" and "Åke" in NFD both become "Ake". ASCII
+/// text is returned as is without allocating.
+pub fn fold_accents(s: &str) -> Cow<'_, str> {
+    if s.is_ascii() {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(
+        s.nfd()
+            .filter(|&c| canonical_combining_class(c) == 0)
+            .map(fold_letter)
+            .nfc()
+            .collect(),
+    )
+}
+
+/// Folds letters that do not decompose into a base letter and a diacritic.
+fn fold_letter(c: char) -> char {
+    match c {
+        'ø' => 'o',
+        'Ø' => 'O',
+        'ł' => 'l',
+        'Ł' => 'L',
+        'đ' => 'd',
+        'Đ' => 'D',
+        'ħ' => 'h',
+        'Ħ' => 'H',
+        _ => c,
+    }
+}
+
+/// Returns if each of the paths exists, without following symlinks. Stat can be slow (e.g. on
+/// Android's emulated storage) so the paths are split between several threads.
+pub fn paths_exist<P: AsRef<Path> + Sync>(paths: &[P]) -> Vec<bool> {
+    // a thread is only worth it for a batch of paths
+    const PATHS_PER_THREAD: usize = 32;
+    let exists = |path: &P| fs::symlink_metadata(path).is_ok();
+    let threads = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(paths.len().div_ceil(PATHS_PER_THREAD));
+    if threads <= 1 {
+        return paths.iter().map(exists).collect();
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(paths.len().div_ceil(threads))
+            .map(|chunk| scope.spawn(move || chunk.iter().map(exists).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("stat thread panicked"))
+            .collect()
+    })
 }
 
 #[cfg(test)]
@@ -95,34 +228,94 @@ mod tests {
     fn test_caseless_contains() {
         let s = "this IS a string";
         let s2 = "y̆es it is";
-        assert!(!caseless_contains(s, s2, false));
+        assert!(!caseless_contains(s, s2));
 
         let s = "YES we can";
         let s2 = "yes We Can";
-        assert!(caseless_contains(s, s2, true));
+        assert!(caseless_contains(s, s2));
 
         let s = "this is a Big Haystack";
         let s2 = "BIG HAYSTACK";
-        assert!(caseless_contains(s, s2, true));
+        assert!(caseless_contains(s, s2));
 
         let s = "/usr/src/cmd/aardvark.c";
         let s2 = "/a";
-        assert!(caseless_contains(s, s2, true));
+        assert!(caseless_contains(s, s2));
 
         let s = "";
         let s2 = "";
-        assert!(!caseless_contains(s, s2, true));
+        assert!(!caseless_contains(s, s2));
 
         let s = "aBc漢字xYz";
         let s2 = "XYZ";
-        assert!(caseless_contains(s, s2, true));
+        assert!(caseless_contains(s, s2));
 
         let s = "aBc漢字xYz2";
         let s2 = "XYZ2";
-        assert!(caseless_contains(s, s2, true));
+        assert!(caseless_contains(s, s2));
 
         let s = "svenskaÅÖÄjapanskaあいうえお";
         let s2 = "å";
-        assert!(caseless_contains(s, s2, false));
+        assert!(caseless_contains(s, s2));
+
+        // folds that change the length, or that differ from lowercasing
+        let matching = [
+            ("Straße", "STRASSE"),
+            ("STRASSE", "straße"),
+            ("Maßstab", "MASS"),
+            ("İstanbul", "i̇stanbul"),
+            ("ΟΔΥΣΣΕΥΣ", "οδυσσευς"),
+            ("ὈΔΥΣΣΕΎΣ", "ὀδυσσεύς"),
+            ("ﬁle.txt", "FILE"),
+            ("\u{212A}elvin", "kelvin"),
+            ("ſtraße", "STRASSE"),
+            ("ǅemal", "ǆ"),
+        ];
+        for (haystack, needle) in matching {
+            assert!(caseless_contains(haystack, needle), "{haystack} {needle}");
+        }
+        let not_matching = [
+            ("Strase", "straße"),
+            ("Straße", "STRAẞEN"),
+            ("ıstanbul", "ISTANBUL"),
+        ];
+        for (haystack, needle) in not_matching {
+            assert!(!caseless_contains(haystack, needle), "{haystack} {needle}");
+        }
+
+        let needle = CaselessNeedle::new("STRASSE");
+        assert!(needle.is_prefix_of("strasse.txt") && needle.is_prefix_of("Straße.txt"));
+        assert!(!needle.is_prefix_of("a/strasse") && !needle.is_prefix_of("stras"));
+        assert!(needle.is_suffix_of("a/Straße") && !needle.is_suffix_of("strasse.txt"));
+        assert!(needle.is_equal_to("ſtraße") && !needle.is_equal_to("strasse "));
+    }
+
+    #[test]
+    fn test_fold_accents() {
+        assert!(matches!(fold_accents("ake.txt"), Cow::Borrowed("ake.txt")));
+        assert_eq!(fold_accents("Åke"), "Ake");
+        assert_eq!(fold_accents("A\u{30A}ke"), "Ake");
+        assert_eq!(fold_accents("Ça déjà été"), "Ca deja ete");
+        assert_eq!(fold_accents("Søren Łódź"), "Soren Lodz");
+        assert_eq!(fold_accents("東京.jpg"), "東京.jpg");
+        // Hangul is composed again
+        assert_eq!(fold_accents("한국"), "한국");
+
+        let (folded, origins) = fold_chars("A\u{30A}ß", |c| case_fold(&fold_accents(c)));
+        assert_eq!(folded, "ass");
+        assert_eq!(origins, [0..3, 3..5, 3..5]);
+    }
+
+    #[test]
+    fn test_paths_exist() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::os::unix::fs::symlink("deleted", dir.path().join("link")).unwrap();
+        // enough paths to be split between threads
+        let paths: Vec<_> = (0..100)
+            .map(|i| dir.path().join(if i % 3 == 0 { "link" } else { "deleted" }))
+            .collect();
+        let exists = paths_exist(&paths);
+        assert_eq!(exists, (0..100).map(|i| i % 3 == 0).collect::<Vec<_>>());
+        assert_eq!(paths_exist::<&str>(&[]), Vec::<bool>::new());
     }
 }
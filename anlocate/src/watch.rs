@@ -0,0 +1,409 @@
+use crate::build::{self, DatabaseOptions, ScanRoot};
+use crate::journal::{self, JournalEntry};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+/// Options for watching the scan roots
+pub struct WatchOptions {
+    /// The options the database was built with.
+    pub database: DatabaseOptions,
+    /// File system events are collected for this long before they are written to the journal.
+    pub batch_interval: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            database: DatabaseOptions::default(),
+            batch_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Watches the scan roots of a database with inotify and records files that are created, deleted
+/// and moved in the database journal, so that they are found by searches without rebuilding the
+/// database. The directories that the roots prune are not watched. If the kernel event queue
+/// overflows the database is updated instead.
+///
+/// The watcher runs on a background thread until `stop` is called or it is dropped.
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Watcher {
+    /// Starts watching `roots`, which must be the roots the database `db_file` was built from.
+    /// This should be called after the database has been built, as changes made before the watch
+    /// is set up are not recorded.
+    pub fn start<P: AsRef<Path>>(
+        db_file: P,
+        roots: &[ScanRoot],
+        options: WatchOptions,
+    ) -> io::Result<Watcher> {
+        let mut state = WatchState::new(db_file.as_ref(), roots, options)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || state.run(&stop_clone));
+        Ok(Watcher {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stops the watcher thread and returns the error that stopped it, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.stop_thread()
+    }
+
+    fn stop_thread(&mut self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => handle.join().unwrap(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.stop_thread();
+    }
+}
+
+struct WatchState {
+    inotify: Inotify,
+    // the watched dirs and the index of the root they are in
+    dirs: HashMap<WatchDescriptor, (usize, PathBuf)>,
+    db_file: PathBuf,
+    roots: Vec<ScanRoot>,
+    options: WatchOptions,
+}
+
+impl WatchState {
+    fn new(db_file: &Path, roots: &[ScanRoot], options: WatchOptions) -> io::Result<WatchState> {
+        let mut state = WatchState {
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            db_file: db_file.to_owned(),
+            roots: roots.to_vec(),
+            options,
+        };
+        for (root, scan_root) in roots.iter().enumerate() {
+            state.add_watches(root, &scan_root.path, &mut Vec::new())?;
+        }
+        Ok(state)
+    }
+
+    fn run(&mut self, stop: &AtomicBool) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        while !stop.load(Ordering::Relaxed) {
+            thread::sleep(self.options.batch_interval);
+
+            let mut entries = Vec::new();
+            let mut overflow = false;
+            loop {
+                let events = match self.inotify.read_events(&mut buffer) {
+                    Ok(events) => events,
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                };
+                // the events borrow the buffer so copy what we need
+                let events: Vec<_> = events
+                    .map(|e| (e.wd, e.mask, e.name.map(|n| n.to_owned())))
+                    .collect();
+                for (wd, mask, name) in events {
+                    if mask.contains(EventMask::Q_OVERFLOW) {
+                        overflow = true;
+                    } else if let Some(name) = name {
+                        self.handle_event(wd, mask, &name, &mut entries)?;
+                    } else if mask.contains(EventMask::IGNORED) {
+                        // the watched dir was deleted
+                        self.dirs.remove(&wd);
+                    }
+                }
+            }
+
+            if overflow {
+                self.update_database()?;
+            } else if !entries.is_empty() {
+                journal::record(&self.db_file, &entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the database with all its roots. This is done when events were lost, since the
+    /// journal cannot be trusted then.
+    fn update_database(&self) -> io::Result<()> {
+        build::update_database_from_roots(&self.db_file, &self.roots, self.options.database.clone())
+    }
+
+    fn handle_event(
+        &mut self,
+        wd: WatchDescriptor,
+        mask: EventMask,
+        name: &OsStr,
+        entries: &mut Vec<JournalEntry>,
+    ) -> io::Result<()> {
+        let Some((root, dir)) = self.dirs.get(&wd) else {
+            return Ok(());
+        };
+        let root = *root;
+        let path = dir.join(name);
+        let is_dir = mask.contains(EventMask::ISDIR);
+        if is_dir && self.roots[root].is_pruned(&path) {
+            return Ok(());
+        }
+
+        if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+            if is_dir {
+                // files may have been created (or moved in with the dir) before the watch was added
+                self.add_watches(root, &path, entries)?;
+            } else if let Some(path) = self.stored_path(root, &path) {
+                entries.push(JournalEntry::Add { root, path });
+            }
+        } else if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+            if is_dir {
+                self.remove_watches(&path);
+                if let Some(path) = self.stored_path(root, &path) {
+                    entries.push(JournalEntry::RemoveDir { root, path });
+                }
+            } else if let Some(path) = self.stored_path(root, &path) {
+                entries.push(JournalEntry::Remove { root, path });
+            }
+        }
+        Ok(())
+    }
+
+    /// Watches `dir` in `root` and all its subdirs that are not pruned, and adds the files in them
+    /// to `entries`.
+    fn add_watches(
+        &mut self,
+        root: usize,
+        dir: &Path,
+        entries: &mut Vec<JournalEntry>,
+    ) -> io::Result<()> {
+        let mask = WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::ONLYDIR
+            | WatchMask::DONT_FOLLOW;
+        let wd = match self.inotify.watches().add(dir, mask) {
+            Ok(wd) => wd,
+            // the dir was removed or is not readable, ignore it like the database builder does
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::NotFound | ErrorKind::PermissionDenied
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+        self.dirs.insert(wd, (root, dir.to_owned()));
+
+        let Ok(dir_entries) = fs::read_dir(dir) else {
+            return Ok(());
+        };
+        for entry in dir_entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                if !self.roots[root].is_pruned(&path) {
+                    self.add_watches(root, &path, entries)?;
+                }
+            } else if let Some(path) = self.stored_path(root, &path) {
+                entries.push(JournalEntry::Add { root, path });
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops watching `dir` and its subdirs. This is needed when a dir is moved out of the scan
+    /// root since the watches follow the moved dir.
+    fn remove_watches(&mut self, dir: &Path) {
+        let mut watches = self.inotify.watches();
+        self.dirs.retain(|wd, (_, path)| {
+            if path.starts_with(dir) {
+                let _ = watches.remove(wd.clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Returns the path in `root` as it is stored in the database, or None if it is not valid UTF-8
+    /// (since such paths cannot be searched for).
+    fn stored_path(&self, root: usize, path: &Path) -> Option<String> {
+        let path = if self.options.database.remove_root {
+            path.strip_prefix(&self.roots[root].path).ok()?
+        } else {
+            path
+        };
+        path.to_str().map(|path| path.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+    use crate::search::{self, MatchType, SearchOptions, SearchQuery};
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_watcher() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("root");
+        create_files(&root, &["a/1.txt", "a/b/2.txt"]);
+        let db_path = tmp_dir.path().join("database.anlocate");
+        let options = watch_options();
+        build::build_database(&db_path, &root, options.database.clone()).unwrap();
+        let watcher = Watcher::start(&db_path, &[ScanRoot::new(&root)], options).unwrap();
+
+        File::create(root.join("a/new.txt")).unwrap();
+        fs::rename(root.join("a/1.txt"), root.join("a/renamed.txt")).unwrap();
+        create_files(&root, &["c/d/3.txt"]);
+        fs::remove_dir_all(root.join("a/b")).unwrap();
+
+        wait_for_hits(
+            &db_path,
+            ".txt",
+            &[(0, "a/new.txt"), (0, "a/renamed.txt"), (0, "c/d/3.txt")],
+        );
+        watcher.stop().unwrap();
+    }
+
+    #[test]
+    fn test_watcher_roots() {
+        let tmp_dir = TempDir::new().unwrap();
+        let internal = tmp_dir.path().join("internal");
+        let sdcard = tmp_dir.path().join("sdcard");
+        create_files(&internal, &["DCIM/a.jpg", "Android/data/x.jpg"]);
+        create_files(&sdcard, &["DCIM/b.jpg"]);
+        let roots = [
+            ScanRoot {
+                prune_paths: vec!["Android/data".into()],
+                prune_names: vec![".thumbnails".into()],
+                ..ScanRoot::new(&internal)
+            },
+            ScanRoot::new(&sdcard),
+        ];
+        let db_path = tmp_dir.path().join("database.anlocate");
+        let options = watch_options();
+        build::build_database_from_roots(&db_path, &roots, options.database.clone()).unwrap();
+        let watcher = Watcher::start(&db_path, &roots, options).unwrap();
+
+        // the events are handled in order, so the pruned files would be recorded before the others
+        create_files(
+            &internal,
+            &["Android/data/y.jpg", "Music/.thumbnails/t.jpg"],
+        );
+        create_files(&sdcard, &["DCIM/c.jpg"]);
+        create_files(&internal, &["Music/song.jpg"]);
+
+        wait_for_hits(
+            &db_path,
+            "jpg",
+            &[
+                (0, "DCIM/a.jpg"),
+                (0, "Music/song.jpg"),
+                (1, "DCIM/b.jpg"),
+                (1, "DCIM/c.jpg"),
+            ],
+        );
+        watcher.stop().unwrap();
+    }
+
+    #[test]
+    fn test_watcher_overflow_updates_all_roots() {
+        let tmp_dir = TempDir::new().unwrap();
+        let internal = tmp_dir.path().join("internal");
+        let sdcard = tmp_dir.path().join("sdcard");
+        create_files(&internal, &["DCIM/a.jpg"]);
+        create_files(&sdcard, &["DCIM/b.jpg"]);
+        let roots = [ScanRoot::new(&internal), ScanRoot::new(&sdcard)];
+        let db_path = tmp_dir.path().join("database.anlocate");
+        let options = watch_options();
+        build::build_database_from_roots(&db_path, &roots, options.database.clone()).unwrap();
+
+        // an overflow updates the database, which keeps the root that was not changed
+        let state = WatchState::new(&db_path, &roots, options).unwrap();
+        create_files(&sdcard, &["DCIM/c.jpg"]);
+        state.update_database().unwrap();
+        assert_eq!(
+            search_hits(&db_path, "jpg"),
+            [(0, "DCIM/a.jpg"), (1, "DCIM/b.jpg"), (1, "DCIM/c.jpg")]
+                .map(|(root, path)| (root, path.to_string()))
+        );
+    }
+
+    fn watch_options() -> WatchOptions {
+        WatchOptions {
+            database: DatabaseOptions {
+                remove_root: true,
+                ..Default::default()
+            },
+            batch_interval: Duration::from_millis(10),
+        }
+    }
+
+    /// Searches the database and its journal until the hits are the expected ones.
+    fn wait_for_hits(db_path: &Path, query: &str, expected: &[(usize, &str)]) {
+        let start = Instant::now();
+        loop {
+            let hits = search_hits(db_path, query);
+            if hits
+                .iter()
+                .map(|(root, path)| (*root, path.as_str()))
+                .eq(expected.iter().copied())
+            {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "watcher did not record changes: {hits:?}"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn search_hits(db_path: &Path, query: &str) -> Vec<(usize, String)> {
+        let journal = Journal::load(db_path).unwrap();
+        let options = SearchOptions {
+            journal: Some(&journal),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(File::open(db_path).unwrap());
+        let query = [SearchQuery::new(query, MatchType::Include)];
+        search::search_with_options(&mut reader, &query, &options)
+            .unwrap()
+            .hits
+            .into_iter()
+            .map(|hit| (hit.root, hit.path))
+            .collect()
+    }
+
+    fn create_files(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+    }
+}
@@ -5,9 +5,39 @@ class AnlocateLibrary {
         init {
             System.loadLibrary("anlocate")
         }
+
+        // caseMode values for nativeFindFiles and nativeCountFiles
+        const val CASE_INSENSITIVE = 0
+        const val CASE_SENSITIVE = 1
+        // case sensitive if the query has an uppercase letter
+        const val CASE_SMART = 2
+
+        // NativeSearchResult.status values
+        const val SEARCH_COMPLETE = 0
+        // the search was cancelled and the files are the ones found before that
+        const val SEARCH_CANCELLED = 1
+        // the timeout was reached and the files are the ones found before that
+        const val SEARCH_TIMED_OUT = 2
     }
 
     external fun nativeBuildDatabase(dbFile: String, scanRoot: String, tempDir: String)
-    external fun nativeFindFiles(dbFile: String, query: Array<String>, includeExclude: BooleanArray): Array<String>
+    external fun nativeUpdateDatabase(dbFile: String, scanRoot: String, tempDir: String)
+    // query uses the anlocate query syntax, e.g. "(jpg OR png) camera NOT thumb". maxResults < 0
+    // and timeoutMs < 0 mean no limit. Returns null if the query is incomplete.
+    external fun nativeFindFiles(dbFile: String, query: String, maxResults: Int, caseMode: Int, ignoreAccents: Boolean, timeoutMs: Long): NativeSearchResult?
+    // returns the number of files that match the query, or -1 if the query is incomplete
+    external fun nativeCountFiles(dbFile: String, query: String, caseMode: Int, ignoreAccents: Boolean): Long
+    // cancels the running nativeFindFiles, a new search also cancels the previous one
+    external fun nativeCancelSearch()
+    // path is the full path of a file in one of the scan roots of the database
+    external fun nativeJournalAdd(dbFile: String, path: String)
+    external fun nativeJournalRemove(dbFile: String, path: String)
+    external fun nativeStartWatcher(dbFile: String, scanRoot: String, tempDir: String)
+    external fun nativeStopWatcher()
     external fun nativeGetStatIndexedFiles(dbFile: String): Long
 }
+
+// created by nativeFindFiles. highlights[i] has the parts of files[i] that matched the query as
+// pairs of start (inclusive) and end (exclusive) indexes. status is one of the
+// AnlocateLibrary.SEARCH_* values
+class NativeSearchResult(val files: Array<String>, val highlights: Array<IntArray>, val status: Int)
@@ -3,6 +3,7 @@ package se.kalind.searchanywhere.data.files
 import android.util.Log
 import kotlinx.coroutines.CoroutineDispatcher
 import kotlinx.coroutines.CoroutineScope
+import kotlinx.coroutines.Job
 import kotlinx.coroutines.flow.Flow
 import kotlinx.coroutines.flow.MutableStateFlow
 import kotlinx.coroutines.flow.flowOn
@@ -14,13 +15,15 @@ import se.kalind.searchanywhere.domain.WorkResult
 import se.kalind.searchanywhere.domain.repo.FileItem
 import se.kalind.searchanywhere.domain.repo.FileSearchResult
 import se.kalind.searchanywhere.domain.repo.FilesRepository
-import se.kalind.searchanywhere.domain.repo.MatchType
 import se.kalind.searchanywhere.domain.repo.ScanRoot
-import se.kalind.searchanywhere.domain.repo.SearchQuery
 import java.io.File
 import kotlin.time.measureTime
 import kotlin.time.measureTimedValue
 
+private const val MAX_SEARCH_RESULTS = 1000
+// a search that takes longer shows the files found so far
+private const val SEARCH_TIMEOUT_MS = 2000L
+
 class DefaultFilesRepository(
     private val lib: AnlocateLibrary,
     private val fileHistoryDao: FileHistoryDao,
@@ -32,19 +35,23 @@ class DefaultFilesRepository(
 ) : FilesRepository {
 
     private val _searchResults =
-        MutableStateFlow(FileSearchResult(searchQuery = emptyList(), files = WorkResult.Loading))
+        MutableStateFlow(FileSearchResult(searchQuery = "", files = WorkResult.Loading))
     override val searchResults: Flow<FileSearchResult> = _searchResults
 
     private val _indexedFilesCount = MutableStateFlow(0L)
     override val indexedFilesCount: Flow<Long> = _indexedFilesCount
 
+    private var searchJob: Job? = null
+
     override suspend fun buildDatabase(scanRoot: ScanRoot) {
         withContext(ioDispatcher) {
             try {
+                // only re-reads the directories that changed since the last build (or does a full
+                // build if there is no database yet)
                 val duration = measureTime {
-                    lib.nativeBuildDatabase(databaseFilePath, scanDirRootPath, tempDirPath)
+                    lib.nativeUpdateDatabase(databaseFilePath, scanDirRootPath, tempDirPath)
                 }
-                Log.i("SearchAnywhere", "native build db: ${duration.inWholeMilliseconds} ms")
+                Log.i("SearchAnywhere", "native update db: ${duration.inWholeMilliseconds} ms")
                 _indexedFilesCount.value = lib.nativeGetStatIndexedFiles(databaseFilePath)
             } catch (e: Exception) {
                 if (e.message?.contains("permission denied", ignoreCase = true) == true) {
@@ -62,21 +69,41 @@ class DefaultFilesRepository(
         }
     }
 
-    override fun setSearchQuery(query: List<SearchQuery>) {
-        appScope.launch(ioDispatcher) {
+    override fun setSearchQuery(query: String) {
+        // the results of the previous search would be stale so stop it
+        searchJob?.cancel()
+        lib.nativeCancelSearch()
+        searchJob = appScope.launch(ioDispatcher) {
             try {
                 if (File(databaseFilePath).isFile) {
-                    val (q, includeExclude) = query.split()
-
-                    val (files, duration) = measureTimedValue {
+                    val (result, duration) = measureTimedValue {
                         lib.nativeFindFiles(
                             dbFile = databaseFilePath,
-                            query = q,
-                            includeExclude = includeExclude,
+                            query = query,
+                            maxResults = MAX_SEARCH_RESULTS,
+                            caseMode = AnlocateLibrary.CASE_INSENSITIVE,
+                            ignoreAccents = true,
+                            timeoutMs = SEARCH_TIMEOUT_MS,
                         )
                     }
+                    if (result == null) {
+                        Log.i("SearchAnywhere", "native search: query incomplete")
+                        return@launch
+                    }
+                    if (result.status == AnlocateLibrary.SEARCH_CANCELLED) {
+                        // a newer search replaces the results
+                        Log.i("SearchAnywhere", "native search cancelled")
+                        return@launch
+                    }
+                    if (result.status == AnlocateLibrary.SEARCH_TIMED_OUT) {
+                        Log.w("SearchAnywhere", "native search timed out, showing partial results")
+                    }
                     Log.i("SearchAnywhere", "native search: ${duration.inWholeMilliseconds} ms")
-                    _searchResults.value = FileSearchResult(query, WorkResult.Success(files))
+                    _searchResults.value = FileSearchResult(
+                        query,
+                        WorkResult.Success(result.files),
+                        result.highlights,
+                    )
                 } else {
                     // The database file may be missing because it hasn't finished building yet in which
                     // case we ignore that and return an empty result
@@ -111,17 +138,6 @@ class DefaultFilesRepository(
     }
 }
 
-// Convert a list of SearchQuery to the native library expected call format (two arrays)
-private fun List<SearchQuery>.split(): Pair<Array<String>, BooleanArray> {
-    val q = mutableListOf<String>()
-    val i = mutableListOf<Boolean>()
-    for (query in this) {
-        q.add(query.query)
-        i.add(query.matchType == MatchType.INCLUDE)
-    }
-    return q.toTypedArray() to i.toBooleanArray()
-}
-
 private fun FileItem.toEntity(): FileHistoryEntity {
     return FileHistoryEntity(
         fullPath = displayName,
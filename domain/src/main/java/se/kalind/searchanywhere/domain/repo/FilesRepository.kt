@@ -13,7 +13,8 @@ interface FilesRepository {
     val indexedFilesCount: Flow<Long>
     fun history(): Flow<List<Pair<FileItem, UnixTimeMs>>>
 
-    fun setSearchQuery(query: List<SearchQuery>)
+    // query uses the anlocate query syntax, e.g. "(jpg OR png) camera NOT thumb"
+    fun setSearchQuery(query: String)
     suspend fun buildDatabase(scanRoot: ScanRoot)
     suspend fun buildDatabaseIfNotExists(scanRoot: ScanRoot)
     fun saveToHistory(item: FileItem)
@@ -42,6 +43,9 @@ data class FileItem(override val displayName: String) : DisplayName, ToItemType
 }
 
 data class FileSearchResult(
-    val searchQuery: List<SearchQuery>,
-    val files: WorkResult<Array<String>>
+    val searchQuery: String,
+    val files: WorkResult<Array<String>>,
+    // highlights[i] has the parts of files[i] that matched the query as pairs of start
+    // (inclusive) and end (exclusive) indexes
+    val highlights: Array<IntArray> = emptyArray(),
 )
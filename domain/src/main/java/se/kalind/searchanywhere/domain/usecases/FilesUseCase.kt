@@ -26,7 +26,7 @@ class FilesUseCase @Inject constructor(
     val indexedFilesCount: Flow<Long> = filesRepository.indexedFilesCount
 
     fun search(query: String) {
-        filesRepository.setSearchQuery(splitFilter(query))
+        filesRepository.setSearchQuery(query)
     }
 
     suspend fun rebuildDatabase() {
@@ -34,11 +34,12 @@ fun <T : DisplayName> filterItems(
     }
 }
 
-fun weighFiles(items: Sequence<FileItem>, queries: List<SearchQuery>): Sequence<WeightedItem<FileItem>> {
-    return if (queries.isEmpty() || queries.first().query.isEmpty()) {
+// Files are already ranked by anlocate so they all get the same weight to keep their order.
+fun weighFiles(items: Sequence<FileItem>, query: String): Sequence<WeightedItem<FileItem>> {
+    return if (query.isBlank()) {
         emptySequence()
     } else {
-        items.map { weigh(it, queries.first().query) }
+        items.map { WeightedItem(0, it) }
     }
 }
 
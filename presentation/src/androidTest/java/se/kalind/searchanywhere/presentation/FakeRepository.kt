@@ -8,9 +8,7 @@ import se.kalind.searchanywhere.domain.WorkResult
 import se.kalind.searchanywhere.domain.repo.FileItem
 import se.kalind.searchanywhere.domain.repo.FileSearchResult
 import se.kalind.searchanywhere.domain.repo.FilesRepository
-import se.kalind.searchanywhere.domain.repo.MatchType
 import se.kalind.searchanywhere.domain.repo.ScanRoot
-import se.kalind.searchanywhere.domain.repo.SearchQuery
 import javax.inject.Inject
 
 class FakeRepository @Inject constructor() : FilesRepository {
@@ -20,12 +18,7 @@ class FakeRepository @Inject constructor() : FilesRepository {
     override val searchResults: Flow<FileSearchResult>
         get() = flowOf(
             FileSearchResult(
-                searchQuery = listOf(
-                    SearchQuery(
-                        query = "file",
-                        matchType = MatchType.INCLUDE,
-                    )
-                ),
+                searchQuery = "file",
                 files = WorkResult.Success(arrayOf("file1", "file2")),
             )
         )
@@ -34,7 +27,7 @@ class FakeRepository @Inject constructor() : FilesRepository {
         return flowOf(emptyList())
     }
 
-    override fun setSearchQuery(query: List<SearchQuery>) {}
+    override fun setSearchQuery(query: String) {}
 
     override suspend fun buildDatabase(scanRoot: ScanRoot) {}
 
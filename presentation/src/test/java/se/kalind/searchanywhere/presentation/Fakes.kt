@@ -14,7 +14,6 @@ import se.kalind.searchanywhere.domain.repo.FileItem
 import se.kalind.searchanywhere.domain.repo.FileSearchResult
 import se.kalind.searchanywhere.domain.repo.FilesRepository
 import se.kalind.searchanywhere.domain.repo.ScanRoot
-import se.kalind.searchanywhere.domain.repo.SearchQuery
 import se.kalind.searchanywhere.domain.repo.SettingItem
 import se.kalind.searchanywhere.domain.repo.SettingItemData
 import se.kalind.searchanywhere.domain.repo.SettingsRepository
@@ -63,7 +62,7 @@ class FakeSettingsRepo : SettingsRepository {
 class FakeFilesRepo() : FilesRepository {
 
     val filesFlow = MutableStateFlow(FileSearchResult(
-        searchQuery = emptyList(),
+        searchQuery = "",
         files = WorkResult.Success(emptyArray()))
     )
 
@@ -79,7 +78,7 @@ class FakeFilesRepo() : FilesRepository {
         return historyFlow
     }
 
-    override fun setSearchQuery(query: List<SearchQuery>) {}
+    override fun setSearchQuery(query: String) {}
 
     override fun saveToHistory(item: FileItem) {}
 
@@ -27,8 +27,6 @@ import se.kalind.searchanywhere.domain.WorkResult
 import se.kalind.searchanywhere.domain.repo.AppIconDrawable
 import se.kalind.searchanywhere.domain.repo.AppItemData
 import se.kalind.searchanywhere.domain.repo.FileSearchResult
-import se.kalind.searchanywhere.domain.repo.MatchType
-import se.kalind.searchanywhere.domain.repo.SearchQuery
 import se.kalind.searchanywhere.domain.repo.SettingItemData
 import se.kalind.searchanywhere.domain.usecases.AppsUseCase
 import se.kalind.searchanywhere.domain.usecases.FilesUseCase
@@ -90,12 +88,7 @@ class SearchScreenViewModelTest {
         // test that ui state is created correctly when there is data
 
         filesRepo.filesFlow.value = FileSearchResult(
-            searchQuery = listOf(
-                SearchQuery(
-                    query = "file",
-                    matchType = MatchType.INCLUDE
-                )
-            ),
+            searchQuery = "file",
             files = WorkResult.Success(arrayOf("file1", "file2.mp3"))
         )
         appsRepo.appsFlow.value = WorkResult.Success(